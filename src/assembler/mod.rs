@@ -0,0 +1,461 @@
+//! A textual assembly language for MLeM programs, and a disassembler back into it.
+//!
+//! Each line holds at most one instruction: a mnemonic followed by its operands,
+//! separated by whitespace (commas are allowed too). Anything after a `;` is a comment.
+//! A line may begin with one or more labels, written as `name:`; using that name as an
+//! operand elsewhere stands for the literal index of the instruction after the label.
+//! As the target of a relative jump (`jrel`, `jrz`, `jrnz`), a label instead stands for
+//! the offset from the jump to the label, so the code can be moved around freely.
+//!
+//! Operands are written as follows:
+//!
+//! | Syntax            | Address          |
+//! |-------------------|------------------|
+//! | `R0`, `SP`, ...   | `RegAbs`         |
+//! | `[0x10]`          | `MemAbs`         |
+//! | `[R1]`            | `MemReg`         |
+//! | `[BP-2]`, `[R1+8]`| `MemRegOffset`   |
+//! | `[R1+R2]`         | `MemRegIndexed`  |
+//! | `[[0x10]]`        | `MemMem`         |
+//! | `42`, `0xFF`, `-1`| `Literal`        |
+//! | `1.5`, `-2.0e3`   | `Literal` (f64)  |
+//! | `loop`            | `Literal` (label)|
+//!
+//! Numbers may be decimal, hexadecimal (`0x`) or binary (`0b`). A decimal number with a
+//! `.` in it is a float, and stands for the bits of that `f64`, for use with the
+//! floating-point instructions. Mnemonics and register names are not case sensitive.
+//!
+//! # Example
+//! ```
+//! # use mlem::assembler::{assemble, disassemble};
+//! # use mlem::virtual_machine::{execute, Outcome};
+//! let program = assemble("
+//!     move 3 R0
+//! loop:
+//!     output R0
+//!     sub R0 1
+//!     jnz loop R0   ; keep going until R0 hits zero
+//!     halt
+//! ").unwrap();
+//!
+//! let (outcome, _, output) = execute(program.clone(), vec![], Some(100));
+//! assert_eq!(outcome, Outcome::Halt);
+//! assert_eq!(output, vec![3, 2, 1]);
+//! assert_eq!(assemble(&disassemble(&program)).unwrap(), program);
+//! ```
+use crate::*;
+use std::collections::HashMap;
+use std::fmt;
+
+#[cfg(test)]
+mod test_assembler;
+
+/// An error encountered while assembling a program, with the position of the offending text.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AssemblyError {
+    /// The line the error occurred on, starting from 1.
+    pub line: usize,
+    /// The column the offending token starts at, starting from 1.
+    pub column: usize,
+    /// What went wrong.
+    pub kind: AssemblyErrorKind,
+}
+
+/// The different kinds of problem the assembler can report.
+#[derive(PartialEq, Debug, Clone)]
+pub enum AssemblyErrorKind {
+    /// The mnemonic doesn't name any instruction.
+    UnknownMnemonic(String),
+    /// The instruction was given the wrong number of operands.
+    WrongOperandCount {
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+    },
+    /// The operand couldn't be understood as any kind of address.
+    InvalidOperand(String),
+    /// The operand refers to a label that is never defined.
+    UndefinedLabel(String),
+    /// The same label was defined more than once.
+    DuplicateLabel(String),
+    /// The label isn't a valid identifier, or collides with a register name.
+    InvalidLabel(String),
+    /// A `[` was opened and never closed, or a `]` appeared without a `[`.
+    UnbalancedBrackets,
+}
+
+impl fmt::Display for AssemblyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AssemblyErrorKind::*;
+        match self {
+            UnknownMnemonic(m) => write!(f, "unknown mnemonic `{}`", m),
+            WrongOperandCount {
+                mnemonic,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} operand(s), but {} were given",
+                mnemonic, expected, found
+            ),
+            InvalidOperand(o) => write!(f, "invalid operand `{}`", o),
+            UndefinedLabel(l) => write!(f, "undefined label `{}`", l),
+            DuplicateLabel(l) => write!(f, "label `{}` is defined more than once", l),
+            InvalidLabel(l) => write!(f, "invalid label name `{}`", l),
+            UnbalancedBrackets => write!(f, "unbalanced brackets"),
+        }
+    }
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// A whitespace-separated piece of a line, and the column it starts at.
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+/// An instruction whose operands haven't been parsed yet, because not every label is known.
+struct PendingLine<'a> {
+    line: usize,
+    mnemonic: Token<'a>,
+    operands: Vec<Token<'a>>,
+}
+
+/// Assemble the given source text into a Program.
+pub fn assemble(source: &str) -> Result<Program, AssemblyError> {
+    let mut labels: HashMap<&str, JumpLocation> = HashMap::new();
+    let mut pending = Vec::new();
+
+    // First pass: find every label and split each instruction into tokens.
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let code = match raw.find(';') {
+            Some(comment) => &raw[..comment],
+            None => raw,
+        };
+        let mut tokens = tokenize(code).map_err(|column| AssemblyError {
+            line,
+            column,
+            kind: AssemblyErrorKind::UnbalancedBrackets,
+        })?;
+
+        let mut first_instruction_token = 0;
+        for token in &tokens {
+            let name = match token.text.strip_suffix(':') {
+                Some(name) => name,
+                None => break,
+            };
+            let error = |kind| AssemblyError {
+                line,
+                column: token.column,
+                kind,
+            };
+            if !is_identifier(name) || Register::from_name(name).is_some() {
+                return Err(error(AssemblyErrorKind::InvalidLabel(name.into())));
+            }
+            if labels.insert(name, pending.len()).is_some() {
+                return Err(error(AssemblyErrorKind::DuplicateLabel(name.into())));
+            }
+            first_instruction_token += 1;
+        }
+
+        let mut rest = tokens.drain(first_instruction_token..);
+        if let Some(mnemonic) = rest.next() {
+            pending.push(PendingLine {
+                line,
+                mnemonic,
+                operands: rest.collect(),
+            });
+        }
+    }
+
+    // Second pass: now that all labels are known, build the instructions.
+    let mut program = Vec::with_capacity(pending.len());
+    for p in pending {
+        let error = |column, kind| AssemblyError {
+            line: p.line,
+            column,
+            kind,
+        };
+        let opcode = Opcode::from_mnemonic(p.mnemonic.text).ok_or_else(|| {
+            error(
+                p.mnemonic.column,
+                AssemblyErrorKind::UnknownMnemonic(p.mnemonic.text.into()),
+            )
+        })?;
+        if p.operands.len() != opcode.arity() {
+            // Point at the first surplus operand if there is one, otherwise at the mnemonic.
+            let column = p
+                .operands
+                .get(opcode.arity())
+                .map_or(p.mnemonic.column, |t| t.column);
+            return Err(error(
+                column,
+                AssemblyErrorKind::WrongOperandCount {
+                    mnemonic: opcode.mnemonic().into(),
+                    expected: opcode.arity(),
+                    found: p.operands.len(),
+                },
+            ));
+        }
+        let mut operands = Vec::with_capacity(p.operands.len());
+        for (i, token) in p.operands.iter().enumerate() {
+            let origin = if i == 0 && opcode.is_relative_jump() {
+                Some(program.len())
+            } else {
+                None
+            };
+            operands.push(
+                parse_operand(token.text, &labels, origin).map_err(|k| error(token.column, k))?,
+            );
+        }
+        // The arity was checked above, so this can't fail.
+        program.push(Instruction::from_parts(opcode, &operands).unwrap());
+    }
+    Ok(program)
+}
+
+/// Render the given program as assembly source, one instruction per line.
+///
+/// Jump targets are written as plain literals, so the output has no labels,
+/// but assembling it again gives back exactly the same program.
+pub fn disassemble(program: &[Instruction]) -> String {
+    let mut out = String::new();
+    for instruction in program {
+        out.push_str(&instruction.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+/// Split a line into tokens on whitespace and commas, keeping bracketed text together.
+/// On unbalanced brackets, returns the column of the problem as the error.
+fn tokenize(line: &str) -> Result<Vec<Token<'_>>, usize> {
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+    let mut depth = 0usize;
+    let mut open_column = 0;
+    for (column, (byte, c)) in line.char_indices().enumerate() {
+        let column = column + 1;
+        match c {
+            '[' => {
+                if depth == 0 {
+                    open_column = column;
+                }
+                depth += 1;
+            }
+            ']' => {
+                if depth == 0 {
+                    return Err(column);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        let separator = depth == 0 && (c.is_whitespace() || c == ',');
+        match (separator, start) {
+            (true, Some((s, col))) => {
+                tokens.push(Token {
+                    text: &line[s..byte],
+                    column: col,
+                });
+                start = None;
+            }
+            (false, None) => start = Some((byte, column)),
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(open_column);
+    }
+    if let Some((s, col)) = start {
+        tokens.push(Token {
+            text: &line[s..],
+            column: col,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Parse a single operand, resolving labels to literal instruction indices, or to offsets
+/// from `origin` if one is given.
+fn parse_operand(
+    text: &str,
+    labels: &HashMap<&str, JumpLocation>,
+    origin: Option<JumpLocation>,
+) -> Result<Address, AssemblyErrorKind> {
+    let invalid = || AssemblyErrorKind::InvalidOperand(text.into());
+    if let Some(inner) = text.strip_prefix('[') {
+        let inner = inner.strip_suffix(']').ok_or_else(invalid)?.trim();
+        if let Some(r) = Register::from_name(inner) {
+            Ok(Address::MemReg(r))
+        } else if let Some(v) = parse_number(inner) {
+            Ok(Address::MemAbs(v))
+        } else if let Some(pointer) = inner.strip_prefix('[').and_then(|p| p.strip_suffix(']')) {
+            parse_number(pointer.trim())
+                .map(Address::MemMem)
+                .ok_or_else(invalid)
+        } else if let Some(split) = inner.find(['+', '-']) {
+            // A base register, then either an index register or a signed displacement.
+            let base = Register::from_name(inner[..split].trim()).ok_or_else(invalid)?;
+            let rest = inner[split + 1..].trim();
+            let negative = inner[split..].starts_with('-');
+            match Register::from_name(rest) {
+                Some(index) if !negative => Ok(Address::MemRegIndexed(base, index)),
+                _ => {
+                    let offset = parse_number(rest).ok_or_else(invalid)? as i64;
+                    Ok(Address::MemRegOffset(
+                        base,
+                        if negative {
+                            offset.wrapping_neg()
+                        } else {
+                            offset
+                        },
+                    ))
+                }
+            }
+        } else {
+            Err(invalid())
+        }
+    } else if let Some(r) = Register::from_name(text) {
+        Ok(Address::RegAbs(r))
+    } else if let Some(v) = parse_number(text) {
+        Ok(Address::Literal(v))
+    } else if let Some(v) = parse_float(text) {
+        Ok(Address::Literal(v.to_bits()))
+    } else if is_identifier(text) {
+        match labels.get(text) {
+            Some(&location) => Ok(Address::Literal(match origin {
+                Some(origin) => (location as Word).wrapping_sub(origin as Word),
+                None => location as Word,
+            })),
+            None => Err(AssemblyErrorKind::UndefinedLabel(text.into())),
+        }
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Parse a decimal, hexadecimal (`0x`) or binary (`0b`) number, optionally negated.
+/// Negative numbers are stored in two's complement.
+fn parse_number(text: &str) -> Option<Word> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (radix, digits) = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        (16, hex)
+    } else if let Some(bin) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        (2, bin)
+    } else {
+        (10, digits)
+    };
+    let digits = digits.replace('_', "");
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = Word::from_str_radix(&digits, radix).ok()?;
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+/// Floats need a `.` so they can't be mistaken for integers, and a leading digit so that
+/// `inf` and `nan` remain usable as labels.
+fn parse_float(text: &str) -> Option<f64> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) || !digits.contains('.') {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Labels start with a letter or underscore and continue with letters, digits, `_` or `.`.
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Small literals read best in decimal; large ones (addresses, bit patterns) in hex.
+fn fmt_word(f: &mut fmt::Formatter, v: Word) -> fmt::Result {
+    if v <= 0xFFFF {
+        write!(f, "{}", v)
+    } else {
+        write!(f, "0x{:X}", v)
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Address::*;
+        match *self {
+            RegAbs(r) => write!(f, "{}", r),
+            MemAbs(l) => {
+                f.write_str("[")?;
+                fmt_word(f, l)?;
+                f.write_str("]")
+            }
+            MemReg(r) => write!(f, "[{}]", r),
+            MemRegOffset(r, offset) => {
+                write!(f, "[{}{}", r, if offset < 0 { '-' } else { '+' })?;
+                fmt_word(f, offset.unsigned_abs())?;
+                f.write_str("]")
+            }
+            MemRegIndexed(r, i) => write!(f, "[{}+{}]", r, i),
+            MemMem(l) => {
+                f.write_str("[[")?;
+                fmt_word(f, l)?;
+                f.write_str("]]")
+            }
+            Literal(v) => fmt_word(f, v),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.opcode().mnemonic())?;
+        for (i, operand) in self.operands().into_iter().enumerate() {
+            match operand {
+                // Relative jumps backwards read much better as negative offsets.
+                Address::Literal(v)
+                    if i == 0 && self.opcode().is_relative_jump() && (v as i64) < 0 =>
+                {
+                    write!(f, " -")?;
+                    fmt_word(f, (v as i64).unsigned_abs())?;
+                }
+                _ => write!(f, " {}", operand)?,
+            }
+        }
+        Ok(())
+    }
+}
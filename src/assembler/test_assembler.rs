@@ -0,0 +1,184 @@
+use super::*;
+use crate::Address::*;
+use crate::Instruction::*;
+use crate::Register::*;
+
+#[test]
+fn test_assemble_operands() {
+    let program = assemble(
+        "move 0xDEADBEEF R0
+         add [R1] R2
+         sub [0x10], -1
+         HALT",
+    )
+    .unwrap();
+    let expected = vec![
+        Move(Literal(0xDEADBEEF), RegAbs(R0)),
+        Add(MemReg(R1), RegAbs(R2)),
+        Sub(MemAbs(0x10), Literal(u64::MAX)),
+        Halt,
+    ];
+    assert!(
+        program == expected,
+        "Assembled {:?} rather than {:?}.",
+        program,
+        expected
+    );
+}
+
+#[test]
+fn test_assemble_labels_and_comments() {
+    let program = assemble(
+        "; count down from R0
+         start: jz end R0 ; done?
+                sub R0 1
+                jump start
+         end:   halt",
+    )
+    .unwrap();
+    let expected = vec![
+        JumpIfZero(Literal(3), RegAbs(R0)),
+        Sub(RegAbs(R0), Literal(1)),
+        Jump(Literal(0)),
+        Halt,
+    ];
+    assert!(
+        program == expected,
+        "Assembled {:?} rather than {:?}.",
+        program,
+        expected
+    );
+}
+
+#[test]
+fn test_assemble_errors() {
+    let err = assemble("noop\n  move R0 [R9]").unwrap_err();
+    assert!(
+        (err.line, err.column) == (2, 11),
+        "Error reported at {}:{} rather than 2:11.",
+        err.line,
+        err.column
+    );
+    assert!(err.kind == AssemblyErrorKind::InvalidOperand("[R9]".into()));
+
+    let err = assemble("jump nowhere").unwrap_err();
+    assert!(err.kind == AssemblyErrorKind::UndefinedLabel("nowhere".into()));
+    assert!(err.column == 6);
+
+    let err = assemble("frobnicate R0").unwrap_err();
+    assert!(err.kind == AssemblyErrorKind::UnknownMnemonic("frobnicate".into()));
+
+    let err = assemble("push R0 R1").unwrap_err();
+    assert!(err.column == 9, "Error reported at column {}.", err.column);
+
+    let err = assemble("R0: halt").unwrap_err();
+    assert!(err.kind == AssemblyErrorKind::InvalidLabel("R0".into()));
+}
+
+#[test]
+fn test_disassemble_round_trip() {
+    let program = vec![
+        NoOp,
+        Zero(MemAbs(7)),
+        Move(Literal(0xDEADBEEF), RegAbs(R0)),
+        Input(MemReg(SP)),
+        Output(RegAbs(BP)),
+        JumpNotZero(Literal(0), MemAbs(0x1_0000)),
+        Push(Literal(1)),
+        Pop(RegAbs(R7)),
+        Illegal,
+        Halt,
+    ];
+    let text = disassemble(&program);
+    assert!(
+        text.lines().nth(2) == Some("move 0xDEADBEEF R0"),
+        "Unexpected disassembly:\n{}",
+        text
+    );
+    let reassembled = assemble(&text).unwrap();
+    assert!(
+        reassembled == program,
+        "Round trip produced {:?} rather than {:?}.",
+        reassembled,
+        program
+    );
+}
+
+#[test]
+fn test_relative_jumps() {
+    let source = "
+        move 3 R0
+        loop: dec R0
+              jrz done R0
+              jrel loop
+        done: jrnz -3 R0
+              halt";
+    let program = assemble(source).unwrap();
+    let expected = vec![
+        Move(Literal(3), RegAbs(R0)),
+        Dec(RegAbs(R0)),
+        JumpRelIfZero(Literal(2), RegAbs(R0)),
+        JumpRel(Literal(-2i64 as u64)),
+        JumpRelNotZero(Literal(-3i64 as u64), RegAbs(R0)),
+        Halt,
+    ];
+    assert!(
+        program == expected,
+        "Assembled {:?} rather than {:?}.",
+        program,
+        expected
+    );
+    let text = disassemble(&program);
+    assert!(
+        text.lines().nth(3) == Some("jrel -2"),
+        "Unexpected disassembly:\n{}",
+        text
+    );
+    assert!(assemble(&text).unwrap() == program);
+}
+
+#[test]
+fn test_float_literals() {
+    let program = assemble("move 1.5 R0\nfadd R0 -2.25e1\nfseq R0 R1").unwrap();
+    let expected = vec![
+        Move(Literal(1.5f64.to_bits()), RegAbs(R0)),
+        FAdd(RegAbs(R0), Literal((-22.5f64).to_bits())),
+        FSetEq(RegAbs(R0), RegAbs(R1)),
+    ];
+    assert!(
+        program == expected,
+        "Assembled {:?} rather than {:?}.",
+        program,
+        expected
+    );
+    assert!(assemble(&disassemble(&program)).unwrap() == program);
+}
+
+#[test]
+fn test_indirect_operands() {
+    let program = assemble("move [BP-2] [R1 + 0x10]\nadd [R1+R2] [[0x20]]").unwrap();
+    let expected = vec![
+        Move(MemRegOffset(BP, -2), MemRegOffset(R1, 0x10)),
+        Add(MemRegIndexed(R1, R2), MemMem(0x20)),
+    ];
+    assert!(
+        program == expected,
+        "Assembled {:?} rather than {:?}.",
+        program,
+        expected
+    );
+    let text = disassemble(&program);
+    assert!(
+        text.lines().next() == Some("move [BP-2] [R1+16]"),
+        "Unexpected disassembly:\n{}",
+        text
+    );
+    assert!(assemble(&text).unwrap() == program);
+    for bad in &["[R1*2]", "[[R1]]", "[R1-R2]", "[8+R1]"] {
+        assert!(
+            assemble(&format!("zero {}", bad)).is_err(),
+            "{} assembled",
+            bad
+        );
+    }
+}
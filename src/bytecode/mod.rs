@@ -0,0 +1,383 @@
+//! A compact, versioned binary encoding for Programs.
+//!
+//! An encoded program starts with the four bytes `MLEM` and a version byte, followed by
+//! each instruction in turn. An instruction is a single opcode byte followed by its
+//! operands; each operand is an addressing-mode tag byte and then either a register
+//! byte or a `Word` encoded as an unsigned LEB128 varint.
+//!
+//! | Tag | Address         | Payload                 |
+//! |-----|-----------------|-------------------------|
+//! | 0   | `RegAbs`        | register                |
+//! | 1   | `MemAbs`        | varint                  |
+//! | 2   | `MemReg`        | register                |
+//! | 3   | `Literal`       | varint                  |
+//! | 4   | `MemRegOffset`  | register, zigzag varint |
+//! | 5   | `MemRegIndexed` | register, register      |
+//! | 6   | `MemMem`        | varint                  |
+//!
+//! The offset of a `MemRegOffset` is zigzag encoded (0, -1, 1, -2, ... as 0, 1, 2, 3, ...)
+//! so that small negative offsets stay small.
+//!
+//! Opcode and register numbers are fixed; new ones are only ever appended, so programs
+//! encoded by older versions of this crate remain decodable.
+//!
+//! `decode` is strict and rejects anything malformed. `decode_lenient` never fails:
+//! anything it can't make sense of becomes an `Illegal` instruction, so any byte string
+//! at all can be used as a genome and run.
+//!
+//! # Example
+//! ```
+//! # use mlem::Instruction::*;
+//! # use mlem::Address::*;
+//! # use mlem::Register::*;
+//! # use mlem::bytecode::{encode, decode};
+//! let program = vec![Move(Literal(0xDEADBEEF), RegAbs(R0)), Output(RegAbs(R0)), Halt];
+//! let bytes = encode(&program);
+//! assert_eq!(decode(&bytes), Ok(program));
+//! ```
+use crate::*;
+use std::fmt;
+
+#[cfg(test)]
+mod test_bytecode;
+
+/// The bytes every encoded program starts with.
+pub const MAGIC: [u8; 4] = *b"MLEM";
+
+/// The version of the encoding produced by `encode`.
+pub const VERSION: u8 = 1;
+
+/// Reasons a byte string can fail to decode strictly.
+#[derive(PartialEq, Debug, Clone)]
+pub enum DecodeError {
+    /// The input doesn't start with `MAGIC`.
+    BadMagic,
+    /// The input was encoded with a version of the format this crate doesn't know.
+    UnsupportedVersion(u8),
+    /// The byte at the given offset isn't a known opcode.
+    UnknownOpcode { offset: usize, byte: u8 },
+    /// The byte at the given offset isn't a known addressing-mode tag.
+    UnknownAddressMode { offset: usize, byte: u8 },
+    /// The byte at the given offset isn't a known register.
+    UnknownRegister { offset: usize, byte: u8 },
+    /// The input ended in the middle of an instruction.
+    Truncated { offset: usize },
+    /// The varint at the given offset doesn't fit in a `Word`.
+    VarintOverflow { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DecodeError::*;
+        match *self {
+            BadMagic => write!(f, "input is not an encoded MLeM program"),
+            UnsupportedVersion(v) => write!(f, "unsupported encoding version {}", v),
+            UnknownOpcode { offset, byte } => {
+                write!(f, "unknown opcode {:#04x} at offset {}", byte, offset)
+            }
+            UnknownAddressMode { offset, byte } => {
+                write!(
+                    f,
+                    "unknown addressing mode {:#04x} at offset {}",
+                    byte, offset
+                )
+            }
+            UnknownRegister { offset, byte } => {
+                write!(f, "unknown register {:#04x} at offset {}", byte, offset)
+            }
+            Truncated { offset } => write!(f, "input truncated at offset {}", offset),
+            VarintOverflow { offset } => write!(f, "varint at offset {} overflows a word", offset),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Encode a program into bytes, including the header.
+pub fn encode(program: &[Instruction]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(MAGIC.len() + 1 + program.len() * 4);
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+    for instruction in program {
+        out.push(opcode_byte(instruction.opcode()));
+        for operand in instruction.operands() {
+            encode_address(&mut out, operand);
+        }
+    }
+    out
+}
+
+/// Decode a program, failing on anything malformed.
+pub fn decode(bytes: &[u8]) -> Result<Program, DecodeError> {
+    if !bytes.starts_with(&MAGIC) {
+        return Err(DecodeError::BadMagic);
+    }
+    let mut reader = Reader {
+        bytes,
+        pos: MAGIC.len(),
+    };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let mut program = Vec::new();
+    while !reader.is_empty() {
+        program.push(reader.instruction()?);
+    }
+    Ok(program)
+}
+
+/// Decode a program, mapping anything undecodable to `Instruction::Illegal`.
+///
+/// The header is skipped if present, but not required. An instruction that can't be
+/// decoded consumes the bytes read up to the problem (always at least its opcode byte),
+/// and decoding resumes from there.
+pub fn decode_lenient(bytes: &[u8]) -> Program {
+    let mut reader = Reader { bytes, pos: 0 };
+    if bytes.len() > MAGIC.len() && bytes.starts_with(&MAGIC) {
+        reader.pos = MAGIC.len() + 1;
+    }
+    let mut program = Vec::new();
+    while !reader.is_empty() {
+        program.push(reader.instruction().unwrap_or(Instruction::Illegal));
+    }
+    program
+}
+
+/// The byte representing each opcode. These must never change; add new ones at the end.
+fn opcode_byte(opcode: Opcode) -> u8 {
+    use crate::Opcode::*;
+    match opcode {
+        NoOp => 0x00,
+        Zero => 0x01,
+        Move => 0x02,
+        Output => 0x03,
+        Input => 0x04,
+        Add => 0x05,
+        Sub => 0x06,
+        Jump => 0x07,
+        JumpIfZero => 0x08,
+        JumpNotZero => 0x09,
+        Push => 0x0A,
+        Pop => 0x0B,
+        Halt => 0x0C,
+        Illegal => 0x0D,
+        Mul => 0x0E,
+        Div => 0x0F,
+        Mod => 0x10,
+        IMul => 0x11,
+        IDiv => 0x12,
+        And => 0x13,
+        Or => 0x14,
+        Xor => 0x15,
+        Not => 0x16,
+        Shl => 0x17,
+        Shr => 0x18,
+        Sar => 0x19,
+        Rotl => 0x1A,
+        Rotr => 0x1B,
+        Neg => 0x1C,
+        Inc => 0x1D,
+        Dec => 0x1E,
+        JumpEq => 0x1F,
+        JumpNe => 0x20,
+        JumpLt => 0x21,
+        JumpGt => 0x22,
+        JumpLe => 0x23,
+        JumpGe => 0x24,
+        JumpILt => 0x25,
+        JumpIGt => 0x26,
+        JumpILe => 0x27,
+        JumpIGe => 0x28,
+        SetEq => 0x29,
+        SetNe => 0x2A,
+        SetLt => 0x2B,
+        SetGt => 0x2C,
+        SetLe => 0x2D,
+        SetGe => 0x2E,
+        SetILt => 0x2F,
+        SetIGt => 0x30,
+        SetILe => 0x31,
+        SetIGe => 0x32,
+        Call => 0x33,
+        Ret => 0x34,
+        JumpRel => 0x35,
+        JumpRelIfZero => 0x36,
+        JumpRelNotZero => 0x37,
+        OutputTo => 0x38,
+        InputFrom => 0x39,
+        InputRemaining => 0x3A,
+        AddWithCarry => 0x3B,
+        SubWithBorrow => 0x3C,
+        JumpFlags => 0x3D,
+        JumpNoFlags => 0x3E,
+        FAdd => 0x3F,
+        FSub => 0x40,
+        FMul => 0x41,
+        FDiv => 0x42,
+        FSqrt => 0x43,
+        FSin => 0x44,
+        FExp => 0x45,
+        FLog => 0x46,
+        IntToFloat => 0x47,
+        FloatToInt => 0x48,
+        FSetEq => 0x49,
+        FSetLt => 0x4A,
+        FSetLe => 0x4B,
+        FSetGt => 0x4C,
+        FSetGe => 0x4D,
+    }
+}
+
+/// The byte representing each register. These must never change; add new ones at the end.
+fn register_byte(register: Register) -> u8 {
+    use crate::Register::*;
+    match register {
+        R0 => 0,
+        R1 => 1,
+        R2 => 2,
+        R3 => 3,
+        R4 => 4,
+        R5 => 5,
+        R6 => 6,
+        R7 => 7,
+        SP => 8,
+        BP => 9,
+        Flags => 10,
+    }
+}
+
+fn encode_address(out: &mut Vec<u8>, address: Address) {
+    use crate::Address::*;
+    match address {
+        RegAbs(r) => {
+            out.push(0);
+            out.push(register_byte(r));
+        }
+        MemAbs(l) => {
+            out.push(1);
+            encode_varint(out, l);
+        }
+        MemReg(r) => {
+            out.push(2);
+            out.push(register_byte(r));
+        }
+        Literal(v) => {
+            out.push(3);
+            encode_varint(out, v);
+        }
+        MemRegOffset(r, offset) => {
+            out.push(4);
+            out.push(register_byte(r));
+            encode_varint(out, ((offset << 1) ^ (offset >> 63)) as Word);
+        }
+        MemRegIndexed(r, i) => {
+            out.push(5);
+            out.push(register_byte(r));
+            out.push(register_byte(i));
+        }
+        MemMem(l) => {
+            out.push(6);
+            encode_varint(out, l);
+        }
+    }
+}
+
+/// Write v as an unsigned LEB128 varint: seven bits per byte, low bits first,
+/// with the high bit set on every byte but the last.
+fn encode_varint(out: &mut Vec<u8>, mut v: Word) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+/// A cursor over the encoded bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        match self.bytes.get(self.pos) {
+            Some(&b) => {
+                self.pos += 1;
+                Ok(b)
+            }
+            None => Err(DecodeError::Truncated { offset: self.pos }),
+        }
+    }
+
+    fn varint(&mut self) -> Result<Word, DecodeError> {
+        let offset = self.pos;
+        let mut value: Word = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            let bits = Word::from(b & 0x7F);
+            // The tenth byte may only carry the single remaining bit.
+            if shift == 63 && bits > 1 {
+                return Err(DecodeError::VarintOverflow { offset });
+            }
+            value |= bits << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(DecodeError::VarintOverflow { offset });
+            }
+        }
+    }
+
+    fn register(&mut self) -> Result<Register, DecodeError> {
+        let offset = self.pos;
+        let b = self.byte()?;
+        Register::ALL
+            .iter()
+            .cloned()
+            .find(|&r| register_byte(r) == b)
+            .ok_or(DecodeError::UnknownRegister { offset, byte: b })
+    }
+
+    fn address(&mut self) -> Result<Address, DecodeError> {
+        let offset = self.pos;
+        match self.byte()? {
+            0 => Ok(Address::RegAbs(self.register()?)),
+            1 => Ok(Address::MemAbs(self.varint()?)),
+            2 => Ok(Address::MemReg(self.register()?)),
+            3 => Ok(Address::Literal(self.varint()?)),
+            4 => {
+                let r = self.register()?;
+                let zigzag = self.varint()?;
+                let offset = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+                Ok(Address::MemRegOffset(r, offset))
+            }
+            5 => Ok(Address::MemRegIndexed(self.register()?, self.register()?)),
+            6 => Ok(Address::MemMem(self.varint()?)),
+            byte => Err(DecodeError::UnknownAddressMode { offset, byte }),
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let offset = self.pos;
+        let b = self.byte()?;
+        let opcode = Opcode::ALL
+            .iter()
+            .cloned()
+            .find(|&op| opcode_byte(op) == b)
+            .ok_or(DecodeError::UnknownOpcode { offset, byte: b })?;
+        let mut operands = Vec::with_capacity(opcode.arity());
+        for _ in 0..opcode.arity() {
+            operands.push(self.address()?);
+        }
+        // Exactly arity operands were read, so this can't fail.
+        Ok(Instruction::from_parts(opcode, &operands).unwrap())
+    }
+}
@@ -0,0 +1,105 @@
+use super::*;
+use crate::virtual_machine::execute;
+use crate::Address::*;
+use crate::Instruction::*;
+use crate::Register::*;
+
+#[test]
+fn test_round_trip() {
+    let program = vec![
+        NoOp,
+        Zero(MemAbs(7)),
+        Move(Literal(u64::MAX), RegAbs(R0)),
+        Add(MemReg(BP), Literal(0x80)),
+        Sub(RegAbs(SP), Literal(0)),
+        Input(RegAbs(R7)),
+        Output(MemAbs(0xDEADBEEF)),
+        Jump(Literal(1)),
+        JumpIfZero(Literal(2), RegAbs(R1)),
+        JumpNotZero(Literal(3), MemReg(R2)),
+        Push(Literal(127)),
+        Pop(RegAbs(R3)),
+        Move(MemRegOffset(BP, -2), MemRegOffset(R4, i64::MAX)),
+        Move(MemRegIndexed(R5, R6), MemMem(0x40)),
+        Illegal,
+        Halt,
+    ];
+    let bytes = encode(&program);
+    assert!(bytes.starts_with(&MAGIC), "Encoding has no header.");
+    let decoded = decode(&bytes);
+    assert!(
+        decoded == Ok(program.clone()),
+        "Decoded {:?} rather than {:?}.",
+        decoded,
+        program
+    );
+    assert!(decode_lenient(&bytes) == program);
+}
+
+#[test]
+fn test_compact() {
+    // Header, opcode, tag, one-byte varint, tag, register
+    let bytes = encode(&[Move(Literal(5), RegAbs(R0))]);
+    assert!(
+        bytes.len() == MAGIC.len() + 1 + 5,
+        "Encoded a small move into {} bytes.",
+        bytes.len()
+    );
+}
+
+#[test]
+fn test_strict_errors() {
+    assert!(decode(b"nope").unwrap_err() == DecodeError::BadMagic);
+    assert!(decode(b"MLEM\x63").unwrap_err() == DecodeError::UnsupportedVersion(0x63));
+    assert!(
+        decode(b"MLEM\x01\xEE").unwrap_err()
+            == DecodeError::UnknownOpcode {
+                offset: 5,
+                byte: 0xEE
+            }
+    );
+    // A push whose literal varint never ends
+    assert!(decode(b"MLEM\x01\x0A\x03\x80").unwrap_err() == DecodeError::Truncated { offset: 8 });
+    assert!(
+        decode(b"MLEM\x01\x0A\x00\x63").unwrap_err()
+            == DecodeError::UnknownRegister {
+                offset: 7,
+                byte: 0x63
+            }
+    );
+    let mut overlong = b"MLEM\x01\x0A\x03".to_vec();
+    overlong.extend_from_slice(&[0xFF; 10]);
+    overlong.push(0x01);
+    assert!(decode(&overlong).unwrap_err() == DecodeError::VarintOverflow { offset: 7 });
+}
+
+#[test]
+fn test_lenient_random_bytes_run() {
+    // Unknown opcode, valid halt, then a truncated move.
+    let program = decode_lenient(&[0xEE, 0x0C, 0x02, 0x03]);
+    assert!(
+        program == vec![Illegal, Halt, Illegal],
+        "Leniently decoded {:?}.",
+        program
+    );
+
+    // Any byte string whatsoever must decode into something the machine can run.
+    let mut state: u32 = 0x1234_5678;
+    for len in 0..64 {
+        let bytes: Vec<u8> = (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let (outcome, cycles, _) = execute(decode_lenient(&bytes), vec![1, 2, 3], Some(100));
+        assert!(
+            cycles <= 100,
+            "Ran for {} cycles, ending in {:?}.",
+            cycles,
+            outcome
+        );
+    }
+}
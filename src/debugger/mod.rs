@@ -0,0 +1,431 @@
+//! A step debugger for MLeM programs, with breakpoints and watchpoints.
+//!
+//! # Example
+//! ```
+//! # use mlem::Instruction::*;
+//! # use mlem::Address::*;
+//! # use mlem::Register::*;
+//! # use mlem::virtual_machine::Machine;
+//! # use mlem::debugger::{Debugger, Location, Access, StopReason};
+//! # use std::collections::VecDeque;
+//! let mut machine = Machine::new(128, VecDeque::new(), Vec::new());
+//! machine.load_program(vec![
+//!     Move(Literal(3), RegAbs(R0)),
+//!     Sub(RegAbs(R0), Literal(1)),
+//!     JumpNotZero(Literal(1), RegAbs(R0)),
+//!     Halt,
+//! ]);
+//!
+//! let mut debugger = Debugger::new(machine);
+//! debugger.add_breakpoint(3);
+//! debugger.add_watchpoint(Location::Register(R0), Access::Write);
+//!
+//! // The first write to R0 is the move.
+//! assert_eq!(
+//!     debugger.continue_for(100),
+//!     StopReason::Watchpoint { location: Location::Register(R0), access: Access::Write, ip: 0 }
+//! );
+//! debugger.clear_watchpoints();
+//! assert_eq!(debugger.continue_for(100), StopReason::Breakpoint(3));
+//! ```
+use crate::virtual_machine::{
+    ExecutionObserver, IoPort, Machine, NoObserver, Outcome, ReturnStack,
+};
+use crate::*;
+
+#[cfg(test)]
+mod test_debugger;
+
+/// A place in the machine that can be watched.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Location {
+    /// A register, like R1.
+    Register(Register),
+    /// A memory address, like 0x10.
+    Memory(Word),
+}
+
+/// The kind of access a watchpoint is interested in.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Access {
+    /// The location was read by an instruction.
+    Read,
+    /// The location's value was changed by an instruction.
+    Write,
+    /// Either of the above.
+    ReadWrite,
+}
+
+/// How a register's value is compared in a conditional breakpoint.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A condition on the value of a register; compares the register (on the left) to the value (on the right).
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: Word,
+}
+
+impl Condition {
+    /// Create a condition which holds when `register <comparison> value` is true.
+    pub fn new(register: Register, comparison: Comparison, value: Word) -> Self {
+        Self {
+            register,
+            comparison,
+            value,
+        }
+    }
+
+    /// Check whether the condition holds for the given machine.
+    pub fn holds<I: IoPort, O: IoPort, T: ExecutionObserver>(
+        &self,
+        machine: &Machine<I, O, T>,
+    ) -> bool {
+        let v = machine.read_addr(Address::RegAbs(self.register));
+        match self.comparison {
+            Comparison::Equal => v == self.value,
+            Comparison::NotEqual => v != self.value,
+            Comparison::Less => v < self.value,
+            Comparison::LessOrEqual => v <= self.value,
+            Comparison::Greater => v > self.value,
+            Comparison::GreaterOrEqual => v >= self.value,
+        }
+    }
+}
+
+/// Why the debugger stopped running the program.
+#[derive(PartialEq, Debug, Clone)]
+pub enum StopReason {
+    /// A single step completed and the program can keep running.
+    Stepped,
+    /// Execution reached a breakpoint at the given IP; the instruction there has not run yet.
+    Breakpoint(usize),
+    /// The instruction at `ip` accessed a watched location. `access` is either `Read` or `Write`.
+    Watchpoint {
+        location: Location,
+        access: Access,
+        ip: usize,
+    },
+    /// The program halted or faulted; the outcome is given.
+    Finished(Outcome),
+    /// The machine suspended for I/O, with `Outcome::NeedsInput` or `Outcome::Output`.
+    /// The program can keep running once the host has dealt with it.
+    Suspended(Outcome),
+    /// The cycle limit ran out before anything else happened.
+    CycleLimit,
+}
+
+struct Breakpoint {
+    ip: usize,
+    condition: Option<Condition>,
+}
+
+struct Watchpoint {
+    location: Location,
+    access: Access,
+}
+
+/// A wrapper around a Machine which runs it under the control of breakpoints and watchpoints.
+pub struct Debugger<I: IoPort, O: IoPort, T: ExecutionObserver = NoObserver> {
+    machine: Machine<I, O, T>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// If the last stop was a breakpoint, the IP it was at, so resuming doesn't stop there again.
+    stopped_at: Option<usize>,
+}
+
+impl<I: IoPort, O: IoPort, T: ExecutionObserver> Debugger<I, O, T> {
+    /// Take control of the given machine.
+    pub fn new(machine: Machine<I, O, T>) -> Self {
+        Self {
+            machine,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            stopped_at: None,
+        }
+    }
+
+    /// Borrow the machine, to examine its state.
+    pub fn machine(&self) -> &Machine<I, O, T> {
+        &self.machine
+    }
+
+    /// Mutably borrow the machine, to change its state between steps.
+    pub fn machine_mut(&mut self) -> &mut Machine<I, O, T> {
+        &mut self.machine
+    }
+
+    /// Give up control of the machine.
+    pub fn into_machine(self) -> Machine<I, O, T> {
+        self.machine
+    }
+
+    /// Stop whenever execution reaches the given IP.
+    pub fn add_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.push(Breakpoint {
+            ip,
+            condition: None,
+        });
+    }
+
+    /// Stop when execution reaches the given IP, but only if the condition holds at that point.
+    pub fn add_conditional_breakpoint(&mut self, ip: usize, condition: Condition) {
+        self.breakpoints.push(Breakpoint {
+            ip,
+            condition: Some(condition),
+        });
+    }
+
+    /// Remove every breakpoint, conditional or not, at the given IP.
+    pub fn remove_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.retain(|b| b.ip != ip);
+    }
+
+    /// Remove all breakpoints.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Stop after any instruction which makes the given kind of access to the given location.
+    ///
+    /// Write watchpoints trigger when the location's value changes, so writing the value
+    /// that's already there goes unnoticed.
+    pub fn add_watchpoint(&mut self, location: Location, access: Access) {
+        self.watchpoints.push(Watchpoint { location, access });
+    }
+
+    /// Remove every watchpoint on the given location.
+    pub fn remove_watchpoint(&mut self, location: Location) {
+        self.watchpoints.retain(|w| w.location != location);
+    }
+
+    /// Remove all watchpoints.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Execute exactly one instruction, regardless of breakpoints.
+    pub fn step(&mut self) -> StopReason {
+        self.stopped_at = None;
+        let ip = self.machine.get_ip();
+        let reads = match self.machine.get_program().get(ip) {
+            Some(&instruction) => self.reads(instruction),
+            None => vec![],
+        };
+        let before: Vec<Word> = self
+            .watchpoints
+            .iter()
+            .map(|w| self.value_at(w.location))
+            .collect();
+
+        match self.machine.execute_next() {
+            Outcome::Continue => {}
+            suspended @ Outcome::NeedsInput { .. } | suspended @ Outcome::Output { .. } => {
+                return StopReason::Suspended(suspended)
+            }
+            other => return StopReason::Finished(other),
+        }
+
+        for (w, old) in self.watchpoints.iter().zip(before) {
+            if w.access != Access::Read && self.value_at(w.location) != old {
+                return StopReason::Watchpoint {
+                    location: w.location,
+                    access: Access::Write,
+                    ip,
+                };
+            }
+        }
+        for w in &self.watchpoints {
+            if w.access != Access::Write && reads.contains(&w.location) {
+                return StopReason::Watchpoint {
+                    location: w.location,
+                    access: Access::Read,
+                    ip,
+                };
+            }
+        }
+        StopReason::Stepped
+    }
+
+    /// Execute until a breakpoint, watchpoint, halt or fault, or until `limit` instructions have run.
+    ///
+    /// If the debugger last stopped at a breakpoint, that breakpoint doesn't stop it again
+    /// before the instruction there has run.
+    pub fn continue_for(&mut self, limit: u64) -> StopReason {
+        for _ in 0..limit {
+            if let Some(reason) = self.check_breakpoints() {
+                return reason;
+            }
+            match self.step() {
+                StopReason::Stepped => {}
+                other => return other,
+            }
+        }
+        StopReason::CycleLimit
+    }
+
+    /// Execute until control reaches the instruction after the current one, also stopping for
+    /// the same reasons as `continue_for`. This runs a backward jump and everything it
+    /// loops over as if it were a single step.
+    pub fn step_over(&mut self, limit: u64) -> StopReason {
+        let target = self.machine.get_ip() + 1;
+        for i in 0..limit {
+            if i > 0 {
+                if self.machine.get_ip() == target {
+                    return StopReason::Stepped;
+                }
+                if let Some(reason) = self.check_breakpoints() {
+                    return reason;
+                }
+            }
+            match self.step() {
+                StopReason::Stepped => {}
+                other => return other,
+            }
+        }
+        if self.machine.get_ip() == target {
+            StopReason::Stepped
+        } else {
+            StopReason::CycleLimit
+        }
+    }
+
+    /// If a breakpoint applies at the current IP, record the stop and return it.
+    fn check_breakpoints(&mut self) -> Option<StopReason> {
+        let ip = self.machine.get_ip();
+        if self.stopped_at == Some(ip) {
+            return None;
+        }
+        let machine = &self.machine;
+        // Unconditional breakpoints always hold.
+        let hit = self.breakpoints.iter().any(|b| {
+            b.ip == ip
+                && match b.condition {
+                    Some(c) => c.holds(machine),
+                    None => true,
+                }
+        });
+        if hit {
+            self.stopped_at = Some(ip);
+            Some(StopReason::Breakpoint(ip))
+        } else {
+            None
+        }
+    }
+
+    /// The current value at a watched location.
+    fn value_at(&self, location: Location) -> Word {
+        match location {
+            Location::Register(r) => self.machine.read_addr(Address::RegAbs(r)),
+            Location::Memory(l) => self.machine.read_addr(Address::MemAbs(l)),
+        }
+    }
+
+    /// Every location the given instruction will read if executed now.
+    fn reads(&self, instruction: Instruction) -> Vec<Location> {
+        use crate::Instruction::*;
+        let mut reads = Vec::new();
+        match instruction {
+            // These only write to their operand, though finding where may take a read.
+            Zero(a) | Input(a) => self.destination_reads(a, &mut reads),
+            InputFrom(a, b) | InputRemaining(a, b) => {
+                self.operand_reads(a, &mut reads);
+                self.destination_reads(b, &mut reads);
+            }
+            Move(a, b) => {
+                self.operand_reads(a, &mut reads);
+                self.destination_reads(b, &mut reads);
+            }
+            Push(a) => {
+                self.operand_reads(a, &mut reads);
+                reads.push(Location::Register(Register::SP));
+            }
+            // Return addresses only go through the hardware stack if it's shared.
+            Call(a) => {
+                self.operand_reads(a, &mut reads);
+                if self.machine.return_stack() == ReturnStack::Shared {
+                    reads.push(Location::Register(Register::SP));
+                }
+            }
+            Ret => {
+                if self.machine.return_stack() == ReturnStack::Shared {
+                    let sp = self.machine.read_addr(Address::RegAbs(Register::SP));
+                    reads.push(Location::Register(Register::SP));
+                    reads.push(Location::Register(Register::BP));
+                    reads.push(Location::Memory(sp));
+                }
+            }
+            Pop(a) => {
+                let sp = self.machine.read_addr(Address::RegAbs(Register::SP));
+                reads.push(Location::Register(Register::SP));
+                reads.push(Location::Register(Register::BP));
+                reads.push(Location::Memory(sp));
+                self.destination_reads(a, &mut reads);
+            }
+            // These also read the flags they carry in or test.
+            AddWithCarry(..) | SubWithBorrow(..) | JumpFlags(..) | JumpNoFlags(..) => {
+                for a in instruction.operands() {
+                    self.operand_reads(a, &mut reads);
+                }
+                reads.push(Location::Register(Register::Flags));
+            }
+            other => {
+                for a in other.operands() {
+                    self.operand_reads(a, &mut reads);
+                }
+            }
+        }
+        reads
+    }
+
+    /// The locations read when an operand's value is read.
+    fn operand_reads(&self, a: Address, reads: &mut Vec<Location>) {
+        match a {
+            Address::RegAbs(r) => reads.push(Location::Register(r)),
+            Address::MemAbs(l) => reads.push(Location::Memory(l)),
+            Address::MemReg(r) => {
+                reads.push(Location::Register(r));
+                reads.push(Location::Memory(self.machine.read_addr(Address::RegAbs(r))));
+            }
+            Address::MemRegOffset(r, offset) => {
+                reads.push(Location::Register(r));
+                let base = self.machine.read_addr(Address::RegAbs(r));
+                reads.push(Location::Memory(base.wrapping_add(offset as Word)));
+            }
+            Address::MemRegIndexed(r, i) => {
+                reads.push(Location::Register(r));
+                reads.push(Location::Register(i));
+                let base = self.machine.read_addr(Address::RegAbs(r));
+                let index = self.machine.read_addr(Address::RegAbs(i));
+                reads.push(Location::Memory(base.wrapping_add(index)));
+            }
+            Address::MemMem(l) => {
+                reads.push(Location::Memory(l));
+                reads.push(Location::Memory(self.machine.read_addr(Address::MemAbs(l))));
+            }
+            Address::Literal(_) => {}
+        }
+    }
+
+    /// The locations read when an operand is only written to.
+    fn destination_reads(&self, a: Address, reads: &mut Vec<Location>) {
+        match a {
+            Address::MemReg(r) | Address::MemRegOffset(r, _) => reads.push(Location::Register(r)),
+            Address::MemRegIndexed(r, i) => {
+                reads.push(Location::Register(r));
+                reads.push(Location::Register(i));
+            }
+            Address::MemMem(l) => reads.push(Location::Memory(l)),
+            Address::RegAbs(_) | Address::MemAbs(_) | Address::Literal(_) => {}
+        }
+    }
+}
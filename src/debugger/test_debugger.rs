@@ -0,0 +1,242 @@
+use super::*;
+use crate::virtual_machine::{ByteReader, ByteWriter};
+use crate::Address::*;
+use crate::Instruction::*;
+use crate::Register::*;
+use std::io::Cursor;
+
+/// Counts R0 down from 3, storing each value at memory address 0x10 through R1.
+fn countdown() -> Program {
+    vec![
+        Move(Literal(3), RegAbs(R0)),        // 0
+        Move(Literal(0x10), RegAbs(R1)),     // 1
+        Move(RegAbs(R0), MemReg(R1)),        // 2
+        Sub(RegAbs(R0), Literal(1)),         // 3
+        JumpNotZero(Literal(2), RegAbs(R0)), // 4
+        Output(MemAbs(0x10)),                // 5
+        Halt,                                // 6
+    ]
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
+    m.load_program(countdown());
+    let mut d = Debugger::new(m);
+
+    d.add_breakpoint(3);
+    for expected_r0 in &[3, 2, 1] {
+        let reason = d.continue_for(100);
+        assert!(
+            reason == StopReason::Breakpoint(3),
+            "Stopped for {:?} rather than at the breakpoint.",
+            reason
+        );
+        assert!(d.machine().read_addr(RegAbs(R0)) == *expected_r0);
+    }
+    d.remove_breakpoint(3);
+    assert!(d.continue_for(2) == StopReason::CycleLimit);
+    let reason = d.continue_for(100);
+    assert!(
+        reason == StopReason::Finished(Outcome::Halt),
+        "Stopped for {:?} rather than halting.",
+        reason
+    );
+}
+
+#[test]
+fn test_conditional_breakpoint() {
+    let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
+    m.load_program(countdown());
+    let mut d = Debugger::new(m);
+
+    d.add_conditional_breakpoint(4, Condition::new(R0, Comparison::Equal, 1));
+    assert!(d.continue_for(100) == StopReason::Breakpoint(4));
+    assert!(d.machine().read_addr(RegAbs(R0)) == 1);
+    assert!(d.continue_for(100) == StopReason::Finished(Outcome::Halt));
+}
+
+#[test]
+fn test_watchpoints() {
+    let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
+    m.load_program(countdown());
+    let mut d = Debugger::new(m);
+
+    // Registers which are both read and written report the write
+    d.add_watchpoint(Location::Register(R0), Access::ReadWrite);
+    assert!(
+        d.continue_for(100)
+            == StopReason::Watchpoint {
+                location: Location::Register(R0),
+                access: Access::Write,
+                ip: 0
+            }
+    );
+    assert!(
+        d.continue_for(100)
+            == StopReason::Watchpoint {
+                location: Location::Register(R0),
+                access: Access::Read,
+                ip: 2
+            }
+    );
+    d.clear_watchpoints();
+
+    // Memory written through a pointer register, on the second time round the loop
+    d.add_watchpoint(Location::Memory(0x10), Access::Write);
+    let reason = d.continue_for(100);
+    assert!(
+        reason
+            == StopReason::Watchpoint {
+                location: Location::Memory(0x10),
+                access: Access::Write,
+                ip: 2
+            },
+        "Stopped for {:?} rather than the write to 0x10.",
+        reason
+    );
+    assert!(d.machine().read_addr(MemAbs(0x10)) == 2);
+    d.clear_watchpoints();
+
+    // Memory read by the output instruction
+    d.add_watchpoint(Location::Memory(0x10), Access::Read);
+    let reason = d.continue_for(100);
+    assert!(
+        reason
+            == StopReason::Watchpoint {
+                location: Location::Memory(0x10),
+                access: Access::Read,
+                ip: 5
+            },
+        "Stopped for {:?} rather than the read of 0x10.",
+        reason
+    );
+}
+
+#[test]
+fn test_call_watchpoints() {
+    // Calls a subroutine which returns straight away.
+    let program = vec![Call(Literal(2)), Halt, Ret];
+    let top = Location::Memory(127);
+    for &return_stack in &[ReturnStack::Shared, ReturnStack::Separate] {
+        let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let mut m = Machine::new(
+            128,
+            ByteReader::new(&mut input),
+            ByteWriter::new(&mut output),
+        );
+        m.set_return_stack(return_stack);
+        m.load_program(program.clone());
+        let mut d = Debugger::new(m);
+        d.add_watchpoint(Location::Register(SP), Access::Read);
+        d.add_watchpoint(top, Access::Read);
+        let reason = d.continue_for(100);
+        if return_stack == ReturnStack::Shared {
+            assert!(
+                reason
+                    == StopReason::Watchpoint {
+                        location: Location::Register(SP),
+                        access: Access::Read,
+                        ip: 0
+                    },
+                "Got {:?}",
+                reason
+            );
+        } else {
+            // A separate return stack leaves SP and the stack's memory alone.
+            assert!(
+                reason == StopReason::Finished(Outcome::Halt),
+                "Got {:?}",
+                reason
+            );
+        }
+    }
+}
+
+#[test]
+fn test_flags_watchpoints() {
+    use crate::virtual_machine::FLAG_CARRY;
+    let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
+    m.load_program(vec![
+        Add(RegAbs(R0), Literal(1)), // 0: sets flags without reading them
+        AddWithCarry(RegAbs(R1), Literal(2)), // 1
+        JumpFlags(Literal(4), Literal(FLAG_CARRY)), // 2
+        JumpNoFlags(Literal(4), Literal(0)), // 3
+        Halt,                        // 4
+    ]);
+    let mut d = Debugger::new(m);
+    d.add_watchpoint(Location::Register(Flags), Access::Read);
+    for &ip in &[1, 2, 3] {
+        let reason = d.continue_for(100);
+        assert!(
+            reason
+                == StopReason::Watchpoint {
+                    location: Location::Register(Flags),
+                    access: Access::Read,
+                    ip
+                },
+            "Expected a read of Flags at {}, got {:?}",
+            ip,
+            reason
+        );
+    }
+    assert!(d.continue_for(100) == StopReason::Finished(Outcome::Halt));
+}
+
+#[test]
+fn test_step_over() {
+    let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
+    m.load_program(countdown());
+    let mut d = Debugger::new(m);
+
+    // Walk up to the loop's conditional jump one instruction at a time.
+    for _ in 0..4 {
+        assert!(d.step() == StopReason::Stepped);
+    }
+    assert!(d.machine().get_ip() == 4);
+
+    // Stepping over the jump runs the rest of the loop.
+    assert!(d.step_over(100) == StopReason::Stepped);
+    assert!(d.machine().get_ip() == 5);
+    assert!(d.machine().read_addr(RegAbs(R0)) == 0);
+
+    // But a breakpoint inside it still stops execution.
+    let mut d = Debugger::new(d.into_machine());
+    d.machine_mut().load_program(countdown());
+    for _ in 0..4 {
+        d.step();
+    }
+    d.add_breakpoint(2);
+    assert!(d.step_over(100) == StopReason::Breakpoint(2));
+}
@@ -0,0 +1,326 @@
+//! Crossover operators, which make two children from two parent programs.
+//!
+//! Splicing programs together moves instructions around, so a jump whose target is a
+//! `Literal` usually ends up pointing somewhere unrelated. Every operator here can optionally
+//! repair such jumps: a jump taken from a parent is rewritten to point at the instruction it
+//! pointed at in that parent, wherever it now is in the child. If that instruction didn't make
+//! it into the child, the jump points at the next one from the same parent which did.
+//! Relative jumps are repaired the same way, by rewriting their offsets; jumps whose
+//! targets are computed at run time are left alone.
+//!
+//! # Example
+//! ```
+//! # use mlem::Instruction::*;
+//! # use mlem::Address::*;
+//! # use mlem::Register::*;
+//! # use mlem::genetic::seeded_rng;
+//! # use mlem::genetic::crossover::{Crossover, OnePoint};
+//! let a = vec![Input(RegAbs(R0)), Jump(Literal(3)), Output(RegAbs(R0)), Halt];
+//! let b = vec![NoOp, NoOp, NoOp, NoOp, NoOp, NoOp];
+//! let (child, _) = OnePoint::new()
+//!     .repair_jumps(true)
+//!     .cross(&a, &b, &mut seeded_rng(1));
+//! // Wherever the Jump ended up, it still goes to the Halt if that was inherited.
+//! if let Some(jump) = child.iter().position(|i| matches!(i, Jump(_))) {
+//!     if let Some(halt) = child.iter().position(|i| *i == Halt) {
+//!         assert_eq!(child[jump], Jump(Literal(halt as u64)));
+//!     }
+//! }
+//! ```
+use crate::*;
+use rand::{Rng, RngCore};
+
+/// Something which can combine two parents into two children.
+pub trait Crossover {
+    /// Make two children from the two parents. The first child takes after `a` and the second
+    /// after `b`, as far as that makes sense for the operator.
+    fn cross(
+        &self,
+        a: &[Instruction],
+        b: &[Instruction],
+        rng: &mut dyn RngCore,
+    ) -> (Program, Program);
+}
+
+impl<C: Crossover + ?Sized> Crossover for Box<C> {
+    fn cross(
+        &self,
+        a: &[Instruction],
+        b: &[Instruction],
+        rng: &mut dyn RngCore,
+    ) -> (Program, Program) {
+        (**self).cross(a, b, rng)
+    }
+}
+
+/// Where an instruction in a child came from: which parent, and where in it.
+#[derive(Copy, Clone)]
+struct Origin {
+    parent: usize,
+    index: usize,
+}
+
+/// A child under construction, remembering where each instruction came from.
+struct Child<'a> {
+    parents: [&'a [Instruction]; 2],
+    origins: Vec<Origin>,
+}
+
+impl<'a> Child<'a> {
+    fn new(parents: [&'a [Instruction]; 2]) -> Self {
+        Child {
+            parents,
+            origins: Vec::new(),
+        }
+    }
+
+    /// Append the instructions of a parent in the given range.
+    fn take(&mut self, parent: usize, range: std::ops::Range<usize>) {
+        self.origins
+            .extend(range.map(|index| Origin { parent, index }));
+    }
+
+    /// Build the program, repairing jump targets if asked to.
+    fn finish(self, repair: bool) -> Program {
+        let mut program: Program = self
+            .origins
+            .iter()
+            .map(|o| self.parents[o.parent][o.index])
+            .collect();
+        if !repair {
+            return program;
+        }
+        // For each parent, the original index and new position of every instruction taken
+        // from it, in order of original index.
+        let mut taken: [Vec<(usize, usize)>; 2] = [Vec::new(), Vec::new()];
+        for (position, o) in self.origins.iter().enumerate() {
+            taken[o.parent].push((o.index, position));
+        }
+        for list in &mut taken {
+            list.sort_unstable();
+        }
+        for (position, o) in self.origins.iter().enumerate() {
+            let instruction = program[position];
+            let opcode = instruction.opcode();
+            let mut operands = instruction.operands();
+            let (old, new) = match operands.first() {
+                Some(&Address::Literal(v)) if opcode.is_absolute_jump() => {
+                    match new_position(&taken[o.parent], v) {
+                        Some(target) => (v, target as Word),
+                        None => continue,
+                    }
+                }
+                Some(&Address::Literal(v)) if opcode.is_relative_jump() => {
+                    let old_target = (o.index as Word).wrapping_add(v);
+                    match new_position(&taken[o.parent], old_target) {
+                        Some(target) => (v, (target as Word).wrapping_sub(position as Word)),
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            if old != new {
+                operands[0] = Address::Literal(new);
+                // The operand count hasn't changed.
+                program[position] = Instruction::from_parts(opcode, &operands).unwrap();
+            }
+        }
+        program
+    }
+}
+
+/// Where the instruction that was at `target` in a parent, or failing that the next one after
+/// it from that parent, now is in the child.
+fn new_position(taken: &[(usize, usize)], target: Word) -> Option<usize> {
+    let i = taken.partition_point(|&(index, _)| (index as Word) < target);
+    taken.get(i).map(|&(_, position)| position)
+}
+
+/// Cut each parent at a random point, and swap the tails. The cut points are chosen
+/// independently, so the children may differ in length from their parents.
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub struct OnePoint {
+    repair: bool,
+}
+
+impl OnePoint {
+    /// A one-point crossover which doesn't repair jumps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose whether to repair `Literal` jump targets.
+    pub fn repair_jumps(mut self, repair: bool) -> Self {
+        self.repair = repair;
+        self
+    }
+}
+
+impl Crossover for OnePoint {
+    fn cross(
+        &self,
+        a: &[Instruction],
+        b: &[Instruction],
+        rng: &mut dyn RngCore,
+    ) -> (Program, Program) {
+        let cut_a = rng.gen_range(0..=a.len());
+        let cut_b = rng.gen_range(0..=b.len());
+        let mut first = Child::new([a, b]);
+        first.take(0, 0..cut_a);
+        first.take(1, cut_b..b.len());
+        let mut second = Child::new([a, b]);
+        second.take(1, 0..cut_b);
+        second.take(0, cut_a..a.len());
+        (first.finish(self.repair), second.finish(self.repair))
+    }
+}
+
+/// Choose a random segment of each parent, and swap them. The segments are chosen
+/// independently, so the children may differ in length from their parents.
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub struct TwoPoint {
+    repair: bool,
+}
+
+impl TwoPoint {
+    /// A two-point crossover which doesn't repair jumps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose whether to repair `Literal` jump targets.
+    pub fn repair_jumps(mut self, repair: bool) -> Self {
+        self.repair = repair;
+        self
+    }
+}
+
+/// A random range within a program of the given length.
+fn random_segment(rng: &mut dyn RngCore, len: usize) -> std::ops::Range<usize> {
+    let start = rng.gen_range(0..=len);
+    let end = rng.gen_range(start..=len);
+    start..end
+}
+
+impl Crossover for TwoPoint {
+    fn cross(
+        &self,
+        a: &[Instruction],
+        b: &[Instruction],
+        rng: &mut dyn RngCore,
+    ) -> (Program, Program) {
+        let seg_a = random_segment(rng, a.len());
+        let seg_b = random_segment(rng, b.len());
+        let mut first = Child::new([a, b]);
+        first.take(0, 0..seg_a.start);
+        first.take(1, seg_b.clone());
+        first.take(0, seg_a.end..a.len());
+        let mut second = Child::new([a, b]);
+        second.take(1, 0..seg_b.start);
+        second.take(0, seg_a);
+        second.take(1, seg_b.end..b.len());
+        (first.finish(self.repair), second.finish(self.repair))
+    }
+}
+
+/// Swap the instructions at each position the parents share with the given probability.
+/// Where one parent is longer, the rest of it stays with its own child.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Uniform {
+    probability: f64,
+    repair: bool,
+}
+
+impl Default for Uniform {
+    fn default() -> Self {
+        Uniform {
+            probability: 0.5,
+            repair: false,
+        }
+    }
+}
+
+impl Uniform {
+    /// A uniform crossover which swaps each position with probability one half, and doesn't
+    /// repair jumps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose the probability of swapping each position, from 0 to 1.
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.probability = probability;
+        self
+    }
+
+    /// Choose whether to repair `Literal` jump targets.
+    pub fn repair_jumps(mut self, repair: bool) -> Self {
+        self.repair = repair;
+        self
+    }
+}
+
+impl Crossover for Uniform {
+    fn cross(
+        &self,
+        a: &[Instruction],
+        b: &[Instruction],
+        rng: &mut dyn RngCore,
+    ) -> (Program, Program) {
+        let mut first = Child::new([a, b]);
+        let mut second = Child::new([a, b]);
+        for i in 0..a.len().min(b.len()) {
+            if rng.gen::<f64>() < self.probability {
+                first.take(1, i..i + 1);
+                second.take(0, i..i + 1);
+            } else {
+                first.take(0, i..i + 1);
+                second.take(1, i..i + 1);
+            }
+        }
+        first.take(0, b.len().min(a.len())..a.len());
+        second.take(1, a.len().min(b.len())..b.len());
+        (first.finish(self.repair), second.finish(self.repair))
+    }
+}
+
+/// Swap a random segment found at the same positions in both parents, so that instructions
+/// only ever move between programs, never along them, and the children keep their parents'
+/// lengths.
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub struct Homologous {
+    repair: bool,
+}
+
+impl Homologous {
+    /// A homologous crossover which doesn't repair jumps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose whether to repair `Literal` jump targets.
+    pub fn repair_jumps(mut self, repair: bool) -> Self {
+        self.repair = repair;
+        self
+    }
+}
+
+impl Crossover for Homologous {
+    fn cross(
+        &self,
+        a: &[Instruction],
+        b: &[Instruction],
+        rng: &mut dyn RngCore,
+    ) -> (Program, Program) {
+        let seg = random_segment(rng, a.len().min(b.len()));
+        let mut first = Child::new([a, b]);
+        first.take(0, 0..seg.start);
+        first.take(1, seg.clone());
+        first.take(0, seg.end..a.len());
+        let mut second = Child::new([a, b]);
+        second.take(1, 0..seg.start);
+        second.take(0, seg.clone());
+        second.take(1, seg.end..b.len());
+        (first.finish(self.repair), second.finish(self.repair))
+    }
+}
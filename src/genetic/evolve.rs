@@ -0,0 +1,546 @@
+//! An evolution engine, which breeds a population of programs towards a fitness function.
+//!
+//! An `Evolver` holds a population of `Individual`s, each a program with its scores. Every
+//! generation it selects parents, makes children from them by crossover and mutation, and
+//! scores the children. Scores come from a `Fitness`, which gives one score per test case,
+//! higher being better; an individual's overall fitness is the sum of its scores.
+//!
+//! # Example
+//! ```
+//! # use mlem::genetic::generate::Generator;
+//! # use mlem::genetic::evolve::{Evolver, Selection, TestCases};
+//! # use mlem::Opcode::*;
+//! // Evolve a program which outputs the sum of its two inputs.
+//! let cases = vec![(vec![1, 2], vec![3]), (vec![5, 5], vec![10]), (vec![7, 0], vec![7])];
+//! let generator = Generator::new()
+//!     .length(2, 6)
+//!     .only_opcodes(&[Input, Add, Output, Halt]);
+//! let mut evolver = Evolver::new(generator, TestCases::new(cases))
+//!     .population_size(50)
+//!     .selection(Selection::Lexicase)
+//!     .seed(3)
+//!     .on_generation(|stats| println!("{}: {}", stats.generation, stats.best_fitness));
+//! let best = evolver.run(30);
+//! // One score per test case; a perfect program scores 0 on each.
+//! assert_eq!(best.scores.len(), 3);
+//! ```
+use super::crossover::{Crossover, TwoPoint};
+use super::generate::Generator;
+use super::mutate::{Mutator, Weighted};
+use super::{seeded_rng, GeneticRng};
+use crate::virtual_machine::{BatchEvaluator, FaultPolicy, MachineBuilder};
+use crate::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::cmp::Ordering;
+
+/// Scores a program on each of a number of test cases. Higher scores are better.
+///
+/// Any `Fn(&Program) -> Vec<f64>` is a `Fitness`, which is never solved.
+pub trait Fitness {
+    /// Score the program, giving one score per test case. Every program should get the same
+    /// number of scores.
+    fn scores(&self, program: &Program) -> Vec<f64>;
+
+    /// Score many programs at once, giving their scores in the same order. By default this
+    /// scores them one by one.
+    fn scores_all(&self, programs: &[Program]) -> Vec<Vec<f64>> {
+        programs.iter().map(|p| self.scores(p)).collect()
+    }
+
+    /// Whether a program with these scores is as good as it can get, so evolution can stop.
+    /// By default nothing is, and evolution runs for as long as it's asked to.
+    fn is_solved(&self, _scores: &[f64]) -> bool {
+        false
+    }
+}
+
+impl<F: Fn(&Program) -> Vec<f64>> Fitness for F {
+    fn scores(&self, program: &Program) -> Vec<f64> {
+        self(program)
+    }
+}
+
+/// A `Fitness` which runs the program on each of a set of inputs, just as `execute_with`
+/// would, and compares what it outputs with what was expected. Runs happen on a
+/// `BatchEvaluator`, across threads, reusing its machines from one call to the next, so
+/// scoring many programs at once shares out the most work.
+///
+/// A case's score is minus the number of output words which are wrong, missing or extra, so
+/// a perfect program scores 0 on every case, and solves them. How the program stops makes no
+/// difference.
+/// By default programs run with 128 words of memory, `FaultPolicy::protected()` and a limit
+/// of 1000 cycles.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TestCases {
+    cases: Vec<(Vec<Word>, Vec<Word>)>,
+    evaluator: BatchEvaluator,
+    limit: u64,
+}
+
+impl TestCases {
+    /// Test programs on the given pairs of input and expected output.
+    pub fn new(cases: Vec<(Vec<Word>, Vec<Word>)>) -> Self {
+        TestCases {
+            cases,
+            evaluator: BatchEvaluator::new(
+                MachineBuilder::new(128).fault_policy(FaultPolicy::protected()),
+            ),
+            limit: 1000,
+        }
+    }
+
+    /// Run programs on machines built from the given configuration.
+    pub fn config(mut self, config: MachineBuilder) -> Self {
+        self.evaluator = self.evaluator.with_config(config);
+        self
+    }
+
+    /// Score many programs at once on the given number of threads. By default, this is as
+    /// many as the system can run in parallel.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.evaluator = self.evaluator.threads(threads);
+        self
+    }
+
+    /// Stop each run after the given number of cycles.
+    pub fn cycle_limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+/// The number of words which differ between what was expected and what was output, counting
+/// any missing or extra words.
+fn output_error(expected: &[Word], output: &[Word]) -> usize {
+    let wrong = expected.iter().zip(output).filter(|&(e, o)| e != o).count();
+    wrong + expected.len().max(output.len()) - expected.len().min(output.len())
+}
+
+impl Fitness for TestCases {
+    fn scores(&self, program: &Program) -> Vec<f64> {
+        // There is exactly one set of scores per program.
+        self.scores_all(std::slice::from_ref(program))
+            .pop()
+            .unwrap()
+    }
+
+    fn scores_all(&self, programs: &[Program]) -> Vec<Vec<f64>> {
+        if self.cases.is_empty() {
+            return vec![Vec::new(); programs.len()];
+        }
+        let runs: Vec<_> = programs
+            .iter()
+            .flat_map(|p| {
+                self.cases
+                    .iter()
+                    .map(move |(input, _)| (&p[..], &input[..]))
+            })
+            .collect();
+        let results = self.evaluator.run(&runs, Some(self.limit));
+        results
+            .chunks(self.cases.len())
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .zip(&self.cases)
+                    .map(|((_, _, output), (_, expected))| -(output_error(expected, output) as f64))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn is_solved(&self, scores: &[f64]) -> bool {
+        scores.iter().all(|&s| s >= 0.0)
+    }
+}
+
+/// A program in the population, with its scores.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Individual {
+    /// The program itself.
+    pub program: Program,
+    /// Its score on each test case.
+    pub scores: Vec<f64>,
+    /// The sum of its scores.
+    pub fitness: f64,
+}
+
+impl Individual {
+    fn scored(program: Program, scores: Vec<f64>) -> Self {
+        Individual {
+            fitness: scores.iter().sum(),
+            scores,
+            program,
+        }
+    }
+
+    /// Score many programs at once.
+    fn all<F: Fitness + ?Sized>(programs: Vec<Program>, fitness: &F) -> Vec<Self> {
+        let scores = fitness.scores_all(&programs);
+        programs
+            .into_iter()
+            .zip(scores)
+            .map(|(program, scores)| Self::scored(program, scores))
+            .collect()
+    }
+}
+
+/// How parents are chosen from the population.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Selection {
+    /// Pick this many individuals at random, and take the fittest.
+    Tournament(usize),
+    /// Pick an individual with probability proportional to its fitness, less the fitness of
+    /// the worst individual.
+    Roulette,
+    /// Pick at random from this fraction of the population, fittest first. 0.2 takes parents
+    /// from the best fifth.
+    Truncation(f64),
+    /// Take the individuals which do best on one test case, then of those the ones which do
+    /// best on another, and so on, with the cases in a random order, until one is left or the
+    /// cases run out; then pick one of those left at random. This favours specialists, and
+    /// needs a `Fitness` which gives more than one score.
+    Lexicase,
+}
+
+/// How each generation replaces the last.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Mode {
+    /// Breed a whole new population, apart from the elite, which carries over unchanged.
+    Generational,
+    /// Breed children one pair at a time, each replacing the least fit individual at once, so
+    /// later parents can be chosen from among earlier children. A generation is as many
+    /// children as there are individuals.
+    SteadyState,
+}
+
+/// Statistics about the population after a generation, as given to the `on_generation`
+/// callback.
+#[derive(PartialEq, Debug, Clone)]
+pub struct GenerationStats {
+    /// The number of the generation; the initial population is generation 0.
+    pub generation: usize,
+    /// The fitness of the fittest individual.
+    pub best_fitness: f64,
+    /// The mean fitness of the population.
+    pub mean_fitness: f64,
+    /// The fitness of the least fit individual.
+    pub worst_fitness: f64,
+    /// The mean length of the programs in the population.
+    pub mean_length: f64,
+    /// The fittest individual's program.
+    pub best_program: Program,
+}
+
+/// A function called with the statistics for each generation.
+type StatsCallback = Box<dyn FnMut(&GenerationStats)>;
+
+/// A population of programs, and everything needed to evolve it.
+///
+/// The defaults are a population of 100, tournaments of 4, an elite of 1, generational
+/// replacement, two-point crossover with jump repair at a rate of 0.7, a mutation rate of 0.9
+/// with `Weighted::standard`, and a seed of 0.
+pub struct Evolver<F: Fitness> {
+    generator: Generator,
+    fitness: F,
+    population_size: usize,
+    selection: Selection,
+    elitism: usize,
+    mode: Mode,
+    crossover: Box<dyn Crossover>,
+    crossover_rate: f64,
+    mutator: Box<dyn Mutator>,
+    mutation_rate: f64,
+    rng: GeneticRng,
+    population: Vec<Individual>,
+    generation: usize,
+    on_generation: Option<StatsCallback>,
+}
+
+impl<F: Fitness> Evolver<F> {
+    /// Evolve programs made by the given generator towards the given fitness function.
+    pub fn new(generator: Generator, fitness: F) -> Self {
+        Evolver {
+            mutator: Box::new(Weighted::standard(&generator)),
+            generator,
+            fitness,
+            population_size: 100,
+            selection: Selection::Tournament(4),
+            elitism: 1,
+            mode: Mode::Generational,
+            crossover: Box::new(TwoPoint::new().repair_jumps(true)),
+            crossover_rate: 0.7,
+            mutation_rate: 0.9,
+            rng: seeded_rng(0),
+            population: Vec::new(),
+            generation: 0,
+            on_generation: None,
+        }
+    }
+
+    /// Choose how many individuals are in the population.
+    pub fn population_size(mut self, size: usize) -> Self {
+        self.population_size = size;
+        self
+    }
+
+    /// Choose how parents are selected.
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Choose how many of the fittest individuals survive each generation unchanged. If that's
+    /// the whole population, it never changes.
+    pub fn elitism(mut self, elitism: usize) -> Self {
+        self.elitism = elitism;
+        self
+    }
+
+    /// Choose how each generation replaces the last.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Choose how parents are combined.
+    pub fn crossover<C: Crossover + 'static>(mut self, crossover: C) -> Self {
+        self.crossover = Box::new(crossover);
+        self
+    }
+
+    /// Choose the probability, from 0 to 1, that a pair of parents is crossed over, rather
+    /// than copied.
+    pub fn crossover_rate(mut self, rate: f64) -> Self {
+        self.crossover_rate = rate;
+        self
+    }
+
+    /// Choose how children are mutated.
+    pub fn mutator<M: Mutator + 'static>(mut self, mutator: M) -> Self {
+        self.mutator = Box::new(mutator);
+        self
+    }
+
+    /// Choose the probability, from 0 to 1, that each child is mutated.
+    pub fn mutation_rate(mut self, rate: f64) -> Self {
+        self.mutation_rate = rate;
+        self
+    }
+
+    /// Seed the RNG from which every random choice is made. Two evolvers configured alike and
+    /// given the same seed evolve exactly the same programs.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = seeded_rng(seed);
+        self
+    }
+
+    /// Call the given function with statistics after every generation, starting with the
+    /// initial population. This replaces any earlier callback.
+    pub fn on_generation<C: FnMut(&GenerationStats) + 'static>(mut self, callback: C) -> Self {
+        self.on_generation = Some(Box::new(callback));
+        self
+    }
+
+    /// The current population, fittest first. Empty until the first generation.
+    pub fn population(&self) -> &[Individual] {
+        &self.population
+    }
+
+    /// The number of generations bred so far.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// The fittest individual, if there's a population yet.
+    pub fn best(&self) -> Option<&Individual> {
+        self.population.first()
+    }
+
+    /// Breed one generation. The first call generates and scores the initial population
+    /// instead.
+    pub fn step(&mut self) {
+        if self.population.is_empty() {
+            let programs = self
+                .generator
+                .population(&mut self.rng, self.population_size);
+            self.population = Individual::all(programs, &self.fitness);
+        } else {
+            match self.mode {
+                Mode::Generational => self.step_generational(),
+                Mode::SteadyState => self.step_steady_state(),
+            }
+            self.generation += 1;
+        }
+        self.sort();
+        self.report();
+    }
+
+    /// Breed generations until there have been the given number since the initial population,
+    /// or until the fitness says the fittest program is solved, whichever comes first.
+    /// Returns the fittest individual.
+    ///
+    /// # Panics
+    /// If the population size is zero.
+    pub fn run(&mut self, generations: usize) -> &Individual {
+        if self.population.is_empty() {
+            self.step();
+        }
+        while self.generation < generations && !self.solved() {
+            self.step();
+        }
+        self.best().expect("the population is empty")
+    }
+
+    fn solved(&self) -> bool {
+        match self.best() {
+            Some(best) => self.fitness.is_solved(&best.scores),
+            None => false,
+        }
+    }
+
+    fn step_generational(&mut self) {
+        let elite = self.elitism.min(self.population.len());
+        let mut children = Vec::new();
+        while elite + children.len() < self.population_size {
+            let (first, second) = self.breed();
+            children.push(first);
+            if elite + children.len() < self.population_size {
+                children.push(second);
+            }
+        }
+        // The whole generation is scored at once, so the fitness can share the work out.
+        let mut next: Vec<Individual> = self.population[..elite].to_vec();
+        next.extend(Individual::all(children, &self.fitness));
+        self.population = next;
+    }
+
+    fn step_steady_state(&mut self) {
+        let mut born = 0;
+        while born < self.population_size {
+            let (first, second) = self.breed();
+            // Both children are scored together, so the fitness can share the work out.
+            for child in Individual::all(vec![first, second], &self.fitness) {
+                born += 1;
+                // The elite are at the front, and are never the least fit; sorting keeps
+                // them there. If the whole population is elite, the child has no place.
+                if self.population.len() >= self.population_size {
+                    if self.elitism >= self.population.len() {
+                        continue;
+                    }
+                    self.population.pop();
+                }
+                self.population.push(child);
+                self.sort();
+            }
+        }
+    }
+
+    /// Select two parents, and make two children from them.
+    fn breed(&mut self) -> (Program, Program) {
+        let a = self.select();
+        let b = self.select();
+        let (a, b) = (&self.population[a].program, &self.population[b].program);
+        let (mut first, mut second) = if self.rng.gen::<f64>() < self.crossover_rate {
+            self.crossover.cross(a, b, &mut self.rng)
+        } else {
+            (a.clone(), b.clone())
+        };
+        for child in [&mut first, &mut second].iter_mut() {
+            if self.rng.gen::<f64>() < self.mutation_rate {
+                self.mutator.mutate(child, &mut self.rng);
+            }
+        }
+        (first, second)
+    }
+
+    /// Pick the index of a parent from the population, which is sorted fittest first.
+    fn select(&mut self) -> usize {
+        let len = self.population.len();
+        match self.selection {
+            Selection::Tournament(size) => (0..size.max(1))
+                .map(|_| self.rng.gen_range(0..len))
+                .min()
+                // The tournament has at least one entrant.
+                .unwrap(),
+            Selection::Roulette => {
+                let worst = self.population[len - 1].fitness;
+                let weights: Vec<f64> = self.population.iter().map(|i| i.fitness - worst).collect();
+                let total: f64 = weights.iter().sum();
+                // An infinite or undefined total leaves nothing to weigh, so choose uniformly.
+                if total <= 0.0 || !total.is_finite() {
+                    return self.rng.gen_range(0..len);
+                }
+                let mut pick = self.rng.gen_range(0.0..total);
+                weights
+                    .iter()
+                    .position(|&w| {
+                        if pick < w {
+                            true
+                        } else {
+                            pick -= w;
+                            false
+                        }
+                    })
+                    // Rounding can leave a sliver at the end; it belongs to the last with a
+                    // chance.
+                    .unwrap_or_else(|| weights.iter().rposition(|&w| w > 0.0).unwrap())
+            }
+            Selection::Truncation(fraction) => {
+                let cutoff = ((len as f64 * fraction).ceil() as usize).max(1).min(len);
+                self.rng.gen_range(0..cutoff)
+            }
+            Selection::Lexicase => {
+                let cases = self.population[0].scores.len();
+                let mut order: Vec<usize> = (0..cases).collect();
+                order.shuffle(&mut self.rng);
+                let mut candidates: Vec<usize> = (0..len).collect();
+                for case in order {
+                    if candidates.len() <= 1 {
+                        break;
+                    }
+                    let score = |i: usize| self.population[i].scores.get(case).cloned();
+                    let best = candidates
+                        .iter()
+                        .filter_map(|&i| score(i))
+                        .fold(f64::NEG_INFINITY, f64::max);
+                    candidates.retain(|&i| score(i) == Some(best));
+                }
+                *candidates.choose(&mut self.rng).unwrap_or(&0)
+            }
+        }
+    }
+
+    /// Sort the population fittest first, keeping the order of equally fit individuals.
+    fn sort(&mut self) {
+        self.population
+            .sort_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap_or(Ordering::Equal));
+    }
+
+    fn report(&mut self) {
+        let callback = match self.on_generation {
+            Some(ref mut callback) => callback,
+            None => return,
+        };
+        let len = self.population.len() as f64;
+        let stats = GenerationStats {
+            generation: self.generation,
+            best_fitness: self.population.first().map_or(0.0, |i| i.fitness),
+            mean_fitness: self.population.iter().map(|i| i.fitness).sum::<f64>() / len,
+            worst_fitness: self.population.last().map_or(0.0, |i| i.fitness),
+            mean_length: self
+                .population
+                .iter()
+                .map(|i| i.program.len() as f64)
+                .sum::<f64>()
+                / len,
+            best_program: self
+                .population
+                .first()
+                .map(|i| i.program.clone())
+                .unwrap_or_default(),
+        };
+        callback(&stats);
+    }
+}
@@ -0,0 +1,276 @@
+//! Random programs, for seeding a population.
+//!
+//! A `Generator` describes what the programs should look like: how long they are, how often
+//! each opcode and addressing mode appears, and what literals they contain. It can then make
+//! any number of programs from an RNG.
+use super::choose_weighted;
+use crate::*;
+use rand::Rng;
+
+/// How many instructions a generated program has.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum LengthDistribution {
+    /// Always exactly this many.
+    Fixed(usize),
+    /// Any number from `min` to `max` inclusive, all equally likely.
+    Uniform { min: usize, max: usize },
+}
+
+/// Where the values of generated literals come from.
+#[derive(PartialEq, Debug, Clone)]
+pub enum LiteralDistribution {
+    /// Any value from `min` to `max` inclusive, all equally likely.
+    Uniform { min: Word, max: Word },
+    /// Any `Word` at all.
+    Any,
+    /// One of the given values, all equally likely. Handy for a problem's known constants.
+    Choice(Vec<Word>),
+}
+
+/// The relative likelihood of each addressing mode appearing in a generated operand.
+/// A mode with a weight of zero never appears.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct AddressWeights {
+    pub reg_abs: u32,
+    pub mem_abs: u32,
+    pub mem_reg: u32,
+    pub literal: u32,
+    pub mem_reg_offset: u32,
+    pub mem_reg_indexed: u32,
+    pub mem_mem: u32,
+}
+
+impl Default for AddressWeights {
+    /// Registers and literals are common; the memory modes are rarer.
+    fn default() -> Self {
+        AddressWeights {
+            reg_abs: 4,
+            mem_abs: 1,
+            mem_reg: 1,
+            literal: 4,
+            mem_reg_offset: 1,
+            mem_reg_indexed: 1,
+            mem_mem: 1,
+        }
+    }
+}
+
+impl AddressWeights {
+    fn as_array(&self) -> [u32; 7] {
+        [
+            self.reg_abs,
+            self.mem_abs,
+            self.mem_reg,
+            self.literal,
+            self.mem_reg_offset,
+            self.mem_reg_indexed,
+            self.mem_mem,
+        ]
+    }
+}
+
+/// A description of random programs, and a source of them.
+///
+/// The default generates programs of 1 to 32 instructions, with every opcode except `Illegal`
+/// equally likely, literals from 0 to 255, memory addresses below 128, and only the general
+/// purpouse registers.
+///
+/// # Example
+/// ```
+/// # use mlem::Opcode;
+/// # use mlem::genetic::seeded_rng;
+/// # use mlem::genetic::generate::{Generator, LiteralDistribution};
+/// let generator = Generator::new()
+///     .length(4, 10)
+///     .opcode_weight(Opcode::Add, 10)
+///     .literals(LiteralDistribution::Choice(vec![0, 1, 10]))
+///     .end_with_halt(true);
+///
+/// let program = generator.program(&mut seeded_rng(7));
+/// assert!(program.len() >= 4 && program.len() <= 10);
+/// assert_eq!(program.last(), Some(&mlem::Instruction::Halt));
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+pub struct Generator {
+    length: LengthDistribution,
+    /// The weight of each opcode, in the order of `Opcode::ALL`
+    opcode_weights: Vec<u32>,
+    address_weights: AddressWeights,
+    literals: LiteralDistribution,
+    registers: Vec<Register>,
+    max_address: Word,
+    max_offset: i64,
+    end_with_halt: bool,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Generator {
+    /// Describe programs as in the default.
+    pub fn new() -> Self {
+        Generator {
+            length: LengthDistribution::Uniform { min: 1, max: 32 },
+            opcode_weights: Opcode::ALL
+                .iter()
+                .map(|&op| if op == Opcode::Illegal { 0 } else { 1 })
+                .collect(),
+            address_weights: AddressWeights::default(),
+            literals: LiteralDistribution::Uniform { min: 0, max: 255 },
+            registers: vec![
+                Register::R0,
+                Register::R1,
+                Register::R2,
+                Register::R3,
+                Register::R4,
+                Register::R5,
+                Register::R6,
+                Register::R7,
+            ],
+            max_address: 127,
+            max_offset: 8,
+            end_with_halt: false,
+        }
+    }
+
+    /// Generate programs of `min` to `max` instructions inclusive, all lengths equally likely.
+    pub fn length(mut self, min: usize, max: usize) -> Self {
+        self.length = LengthDistribution::Uniform { min, max };
+        self
+    }
+
+    /// Choose how long generated programs are.
+    pub fn length_distribution(mut self, length: LengthDistribution) -> Self {
+        self.length = length;
+        self
+    }
+
+    /// Set how likely an opcode is, relative to the others. Every opcode but `Illegal` starts
+    /// with a weight of 1; a weight of 0 means the opcode never appears.
+    pub fn opcode_weight(mut self, opcode: Opcode, weight: u32) -> Self {
+        // Every opcode is in ALL.
+        let i = Opcode::ALL.iter().position(|&op| op == opcode).unwrap();
+        self.opcode_weights[i] = weight;
+        self
+    }
+
+    /// Use only the given opcodes, all equally likely.
+    pub fn only_opcodes(mut self, opcodes: &[Opcode]) -> Self {
+        self.opcode_weights = Opcode::ALL
+            .iter()
+            .map(|op| opcodes.contains(op) as u32)
+            .collect();
+        self
+    }
+
+    /// Choose how likely each addressing mode is.
+    pub fn address_weights(mut self, weights: AddressWeights) -> Self {
+        self.address_weights = weights;
+        self
+    }
+
+    /// Choose where the values of literals come from.
+    pub fn literals(mut self, literals: LiteralDistribution) -> Self {
+        self.literals = literals;
+        self
+    }
+
+    /// Choose which registers operands may name, all equally likely.
+    pub fn registers(mut self, registers: Vec<Register>) -> Self {
+        self.registers = registers;
+        self
+    }
+
+    /// Choose the highest memory address `MemAbs` and `MemMem` operands may name.
+    pub fn max_address(mut self, max_address: Word) -> Self {
+        self.max_address = max_address;
+        self
+    }
+
+    /// Choose the largest displacement, either way, of a `MemRegOffset` operand.
+    pub fn max_offset(mut self, max_offset: i64) -> Self {
+        self.max_offset = max_offset.saturating_abs();
+        self
+    }
+
+    /// Choose whether every program ends with `Halt`. The `Halt` counts towards the length,
+    /// except that a program drawn with no instructions at all is just the `Halt`.
+    pub fn end_with_halt(mut self, end_with_halt: bool) -> Self {
+        self.end_with_halt = end_with_halt;
+        self
+    }
+
+    /// Generate a whole program.
+    ///
+    /// # Panics
+    /// If every opcode or every addressing mode has a weight of zero, if no registers are
+    /// allowed, or if `LiteralDistribution::Choice` is given no values.
+    pub fn program<R: Rng + ?Sized>(&self, rng: &mut R) -> Program {
+        let len = match self.length {
+            LengthDistribution::Fixed(len) => len,
+            LengthDistribution::Uniform { min, max } => rng.gen_range(min..=max.max(min)),
+        };
+        let mut program: Program = (0..len).map(|_| self.instruction(rng)).collect();
+        if self.end_with_halt {
+            match program.last_mut() {
+                Some(last) => *last = Instruction::Halt,
+                None => program.push(Instruction::Halt),
+            }
+        }
+        program
+    }
+
+    /// Generate `size` programs.
+    pub fn population<R: Rng + ?Sized>(&self, rng: &mut R, size: usize) -> Vec<Program> {
+        (0..size).map(|_| self.program(rng)).collect()
+    }
+
+    /// Generate a single instruction.
+    pub fn instruction<R: Rng + ?Sized>(&self, rng: &mut R) -> Instruction {
+        let opcode = self.opcode(rng);
+        let operands: Vec<Address> = (0..opcode.arity()).map(|_| self.address(rng)).collect();
+        // There are exactly as many operands as the opcode needs.
+        Instruction::from_parts(opcode, &operands).unwrap()
+    }
+
+    /// Generate a single opcode.
+    pub fn opcode<R: Rng + ?Sized>(&self, rng: &mut R) -> Opcode {
+        let i = choose_weighted(rng, &self.opcode_weights).expect("every opcode weight is zero");
+        Opcode::ALL[i]
+    }
+
+    /// Generate a single operand.
+    pub fn address<R: Rng + ?Sized>(&self, rng: &mut R) -> Address {
+        let mode = choose_weighted(rng, &self.address_weights.as_array())
+            .expect("every addressing mode weight is zero");
+        match mode {
+            0 => Address::RegAbs(self.register(rng)),
+            1 => Address::MemAbs(rng.gen_range(0..=self.max_address)),
+            2 => Address::MemReg(self.register(rng)),
+            3 => Address::Literal(self.literal(rng)),
+            4 => Address::MemRegOffset(
+                self.register(rng),
+                rng.gen_range(-self.max_offset..=self.max_offset),
+            ),
+            5 => Address::MemRegIndexed(self.register(rng), self.register(rng)),
+            _ => Address::MemMem(rng.gen_range(0..=self.max_address)),
+        }
+    }
+
+    /// Generate a single register.
+    pub fn register<R: Rng + ?Sized>(&self, rng: &mut R) -> Register {
+        self.registers[rng.gen_range(0..self.registers.len())]
+    }
+
+    /// Generate a single literal value.
+    pub fn literal<R: Rng + ?Sized>(&self, rng: &mut R) -> Word {
+        match self.literals {
+            LiteralDistribution::Uniform { min, max } => rng.gen_range(min..=max.max(min)),
+            LiteralDistribution::Any => rng.gen(),
+            LiteralDistribution::Choice(ref values) => values[rng.gen_range(0..values.len())],
+        }
+    }
+}
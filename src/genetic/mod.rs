@@ -0,0 +1,50 @@
+//! Tools for evolving MLeM programs.
+//!
+//! Everything here that makes random choices takes its randomness from an RNG passed in by
+//! the caller, so a run can be reproduced exactly by starting from the same seed. `seeded_rng`
+//! gives a fast RNG whose output is guaranteed not to change between releases.
+//!
+//! This module needs the `genetic` feature, which is on by default.
+//!
+//! # Example
+//! ```
+//! # use mlem::genetic::{seeded_rng, generate::Generator};
+//! let generator = Generator::new().length(8, 16).end_with_halt(true);
+//! let population = generator.population(&mut seeded_rng(42), 100);
+//! assert_eq!(population, generator.population(&mut seeded_rng(42), 100));
+//! ```
+use rand::SeedableRng;
+
+pub mod crossover;
+pub mod evolve;
+pub mod generate;
+pub mod mutate;
+#[cfg(test)]
+mod test_genetic;
+
+/// The RNG returned by `seeded_rng`.
+pub type GeneticRng = rand_chacha::ChaCha8Rng;
+
+/// An RNG seeded from the given number, which will produce the same sequence on any
+/// platform and with any version of this crate.
+pub fn seeded_rng(seed: u64) -> GeneticRng {
+    GeneticRng::seed_from_u64(seed)
+}
+
+/// Pick an index into `weights` at random, with probability proportional to its weight.
+/// Returns `None` if every weight is zero.
+fn choose_weighted<R: rand::Rng + ?Sized>(rng: &mut R, weights: &[u32]) -> Option<usize> {
+    let total: u64 = weights.iter().map(|&w| u64::from(w)).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut pick = rng.gen_range(0..total);
+    weights.iter().position(|&w| {
+        if pick < u64::from(w) {
+            true
+        } else {
+            pick -= u64::from(w);
+            false
+        }
+    })
+}
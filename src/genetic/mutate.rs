@@ -0,0 +1,448 @@
+//! Mutation operators over programs.
+//!
+//! Each operator is a `Mutator`, which changes a program in place using randomness from the
+//! RNG it's given. Operators that need new material, like a fresh instruction or literal,
+//! take it from a `Generator`, so they produce the same kinds of thing the initial population
+//! was made of.
+//!
+//! Operators can be combined: `Weighted` picks one of several at random, `with_probability`
+//! applies one only some of the time, `then` applies one after another, and `repeat` applies
+//! one several times.
+//!
+//! # Example
+//! ```
+//! # use mlem::genetic::seeded_rng;
+//! # use mlem::genetic::generate::Generator;
+//! # use mlem::genetic::mutate::*;
+//! let generator = Generator::new();
+//! let mutator = Weighted::new()
+//!     .add(3, LiteralMutation::new(generator.clone()))
+//!     .add(1, Insertion::new(generator.clone()))
+//!     .add(1, Deletion)
+//!     .then(BlockInversion::new(4).with_probability(0.1));
+//!
+//! let mut rng = seeded_rng(0);
+//! let mut program = generator.program(&mut rng);
+//! mutator.mutate(&mut program, &mut rng);
+//! ```
+use super::choose_weighted;
+use super::generate::Generator;
+use crate::*;
+use rand::{Rng, RngCore};
+
+/// Something which can change a program at random.
+///
+/// The RNG is a trait object so that mutators can themselves be boxed up and combined.
+pub trait Mutator {
+    /// Change the program in place. A mutator which finds nothing to change (for instance,
+    /// a literal mutation on a program with no literals) leaves the program as it is.
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore);
+
+    /// Apply this mutator only with the given probability, from 0 to 1.
+    fn with_probability(self, probability: f64) -> WithProbability<Self>
+    where
+        Self: Sized,
+    {
+        WithProbability {
+            probability,
+            mutator: self,
+        }
+    }
+
+    /// Apply this mutator, then the other.
+    fn then<M: Mutator>(self, other: M) -> Then<Self, M>
+    where
+        Self: Sized,
+    {
+        Then(self, other)
+    }
+
+    /// Apply this mutator the given number of times.
+    fn repeat(self, times: usize) -> Repeat<Self>
+    where
+        Self: Sized,
+    {
+        Repeat {
+            times,
+            mutator: self,
+        }
+    }
+}
+
+impl<M: Mutator + ?Sized> Mutator for Box<M> {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        (**self).mutate(program, rng)
+    }
+}
+
+impl<M: Mutator + ?Sized> Mutator for &M {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        (**self).mutate(program, rng)
+    }
+}
+
+/// Pick a random index into something of the given length, if it isn't empty.
+fn random_index(rng: &mut dyn RngCore, len: usize) -> Option<usize> {
+    if len == 0 {
+        None
+    } else {
+        Some(rng.gen_range(0..len))
+    }
+}
+
+/// Pick, uniformly at random, one operand in the program which satisfies the predicate,
+/// giving the index of its instruction and its position among that instruction's operands.
+fn random_operand<F: Fn(Address) -> bool>(
+    program: &[Instruction],
+    rng: &mut dyn RngCore,
+    wanted: F,
+) -> Option<(usize, usize)> {
+    let candidates: Vec<(usize, usize)> = program
+        .iter()
+        .enumerate()
+        .flat_map(|(i, instruction)| {
+            instruction
+                .operands()
+                .into_iter()
+                .enumerate()
+                .filter(|&(_, a)| wanted(a))
+                .map(move |(j, _)| (i, j))
+        })
+        .collect();
+    random_index(rng, candidates.len()).map(|k| candidates[k])
+}
+
+/// Apply f to one operand of the instruction at the given index.
+fn replace_operand<F: FnOnce(Address) -> Address>(
+    program: &mut Program,
+    (i, j): (usize, usize),
+    f: F,
+) {
+    let instruction = program[i];
+    let mut operands = instruction.operands();
+    operands[j] = f(operands[j]);
+    // The operand count hasn't changed.
+    program[i] = Instruction::from_parts(instruction.opcode(), &operands).unwrap();
+}
+
+/// Replace the opcode of a random instruction with one from the generator. Operands are kept
+/// where the new opcode has room for them, and made up where it needs more.
+#[derive(PartialEq, Debug, Clone)]
+pub struct OpcodeMutation {
+    generator: Generator,
+}
+
+impl OpcodeMutation {
+    /// Draw new opcodes, and any new operands they need, from the given generator.
+    pub fn new(generator: Generator) -> Self {
+        OpcodeMutation { generator }
+    }
+}
+
+impl Mutator for OpcodeMutation {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        if let Some(i) = random_index(rng, program.len()) {
+            let opcode = self.generator.opcode(rng);
+            let mut operands = program[i].operands();
+            operands.truncate(opcode.arity());
+            while operands.len() < opcode.arity() {
+                operands.push(self.generator.address(rng));
+            }
+            // There are now exactly as many operands as the opcode needs.
+            program[i] = Instruction::from_parts(opcode, &operands).unwrap();
+        }
+    }
+}
+
+/// Replace a random operand with a new one from the generator, which will usually have a
+/// different addressing mode.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AddressMutation {
+    generator: Generator,
+}
+
+impl AddressMutation {
+    /// Draw new operands from the given generator.
+    pub fn new(generator: Generator) -> Self {
+        AddressMutation { generator }
+    }
+}
+
+impl Mutator for AddressMutation {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        if let Some(at) = random_operand(program, rng, |_| true) {
+            let new = self.generator.address(rng);
+            replace_operand(program, at, |_| new);
+        }
+    }
+}
+
+/// Replace one register named by a random operand with one from the generator, keeping the
+/// addressing mode.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RegisterMutation {
+    generator: Generator,
+}
+
+impl RegisterMutation {
+    /// Draw new registers from the given generator.
+    pub fn new(generator: Generator) -> Self {
+        RegisterMutation { generator }
+    }
+}
+
+impl Mutator for RegisterMutation {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        use crate::Address::*;
+        let names_register = |a| {
+            matches!(
+                a,
+                RegAbs(_) | MemReg(_) | MemRegOffset(..) | MemRegIndexed(..)
+            )
+        };
+        if let Some(at) = random_operand(program, rng, names_register) {
+            let new = self.generator.register(rng);
+            let index = rng.gen::<bool>();
+            replace_operand(program, at, |a| match a {
+                RegAbs(_) => RegAbs(new),
+                MemReg(_) => MemReg(new),
+                MemRegOffset(_, offset) => MemRegOffset(new, offset),
+                MemRegIndexed(r, _) if index => MemRegIndexed(r, new),
+                MemRegIndexed(_, i) => MemRegIndexed(new, i),
+                other => other,
+            });
+        }
+    }
+}
+
+/// Change the value of a random `Literal` operand.
+///
+/// By default the new value comes from the generator; with `step`, the literal is instead
+/// nudged up or down by at most that much, which is gentler on values that are nearly right.
+#[derive(PartialEq, Debug, Clone)]
+pub struct LiteralMutation {
+    generator: Generator,
+    step: Option<Word>,
+}
+
+impl LiteralMutation {
+    /// Draw new literal values from the given generator.
+    pub fn new(generator: Generator) -> Self {
+        LiteralMutation {
+            generator,
+            step: None,
+        }
+    }
+
+    /// Nudge literals by at most the given amount, rather than replacing them outright.
+    pub fn step(mut self, step: Word) -> Self {
+        self.step = Some(step);
+        self
+    }
+}
+
+impl Mutator for LiteralMutation {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        let is_literal = |a| matches!(a, Address::Literal(_));
+        if let Some(at) = random_operand(program, rng, is_literal) {
+            let value = match self.step {
+                None => self.generator.literal(rng),
+                Some(step) => {
+                    let old = program[at.0].operands()[at.1];
+                    let old = if let Address::Literal(v) = old { v } else { 0 };
+                    let delta = rng.gen_range(0..=step);
+                    if rng.gen::<bool>() {
+                        old.wrapping_add(delta)
+                    } else {
+                        old.wrapping_sub(delta)
+                    }
+                }
+            };
+            replace_operand(program, at, |_| Address::Literal(value));
+        }
+    }
+}
+
+/// Insert a new instruction from the generator at a random position.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Insertion {
+    generator: Generator,
+}
+
+impl Insertion {
+    /// Draw new instructions from the given generator.
+    pub fn new(generator: Generator) -> Self {
+        Insertion { generator }
+    }
+}
+
+impl Mutator for Insertion {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        let at = rng.gen_range(0..=program.len());
+        let instruction = self.generator.instruction(rng);
+        program.insert(at, instruction);
+    }
+}
+
+/// Remove a random instruction.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Deletion;
+
+impl Mutator for Deletion {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        if let Some(i) = random_index(rng, program.len()) {
+            program.remove(i);
+        }
+    }
+}
+
+/// Copy a random instruction, inserting the copy just after it.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Duplication;
+
+impl Mutator for Duplication {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        if let Some(i) = random_index(rng, program.len()) {
+            program.insert(i + 1, program[i]);
+        }
+    }
+}
+
+/// Swap two random, non-overlapping blocks of the same length.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct BlockSwap {
+    max_len: usize,
+}
+
+impl BlockSwap {
+    /// Swap blocks of at most the given number of instructions.
+    pub fn new(max_len: usize) -> Self {
+        BlockSwap { max_len }
+    }
+}
+
+impl Mutator for BlockSwap {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        let max_len = self.max_len.min(program.len() / 2);
+        if max_len == 0 {
+            return;
+        }
+        let len = rng.gen_range(1..=max_len);
+        // Pick the first block from anywhere that leaves room for the second after it.
+        let first = rng.gen_range(0..=program.len() - 2 * len);
+        let second = rng.gen_range(first + len..=program.len() - len);
+        let (front, back) = program.split_at_mut(second);
+        front[first..first + len].swap_with_slice(&mut back[..len]);
+    }
+}
+
+/// Reverse the order of a random block of instructions.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct BlockInversion {
+    max_len: usize,
+}
+
+impl BlockInversion {
+    /// Reverse blocks of at most the given number of instructions.
+    pub fn new(max_len: usize) -> Self {
+        BlockInversion { max_len }
+    }
+}
+
+impl Mutator for BlockInversion {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        let max_len = self.max_len.min(program.len());
+        if max_len < 2 {
+            return;
+        }
+        let len = rng.gen_range(2..=max_len);
+        let start = rng.gen_range(0..=program.len() - len);
+        program[start..start + len].reverse();
+    }
+}
+
+/// Apply a mutator only some of the time. Made by `Mutator::with_probability`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct WithProbability<M> {
+    probability: f64,
+    mutator: M,
+}
+
+impl<M: Mutator> Mutator for WithProbability<M> {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        if rng.gen::<f64>() < self.probability {
+            self.mutator.mutate(program, rng);
+        }
+    }
+}
+
+/// Apply one mutator and then another. Made by `Mutator::then`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Then<A, B>(A, B);
+
+impl<A: Mutator, B: Mutator> Mutator for Then<A, B> {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        self.0.mutate(program, rng);
+        self.1.mutate(program, rng);
+    }
+}
+
+/// Apply a mutator several times. Made by `Mutator::repeat`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Repeat<M> {
+    times: usize,
+    mutator: M,
+}
+
+impl<M: Mutator> Mutator for Repeat<M> {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        for _ in 0..self.times {
+            self.mutator.mutate(program, rng);
+        }
+    }
+}
+
+/// Pick one of several mutators at random, each with probability proportional to its weight,
+/// and apply it. With no mutators, it does nothing.
+#[derive(Default)]
+pub struct Weighted {
+    weights: Vec<u32>,
+    mutators: Vec<Box<dyn Mutator + Send + Sync>>,
+}
+
+impl Weighted {
+    /// Start with no mutators.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a mutator with the given weight.
+    pub fn add<M: Mutator + Send + Sync + 'static>(mut self, weight: u32, mutator: M) -> Self {
+        self.weights.push(weight);
+        self.mutators.push(Box::new(mutator));
+        self
+    }
+
+    /// A reasonable mix of every operator in this module, drawing new material from the
+    /// given generator. Point mutations are the most common.
+    pub fn standard(generator: &Generator) -> Self {
+        Weighted::new()
+            .add(4, OpcodeMutation::new(generator.clone()))
+            .add(4, AddressMutation::new(generator.clone()))
+            .add(4, RegisterMutation::new(generator.clone()))
+            .add(4, LiteralMutation::new(generator.clone()))
+            .add(2, Insertion::new(generator.clone()))
+            .add(2, Deletion)
+            .add(1, Duplication)
+            .add(1, BlockSwap::new(4))
+            .add(1, BlockInversion::new(4))
+    }
+}
+
+impl Mutator for Weighted {
+    fn mutate(&self, program: &mut Program, rng: &mut dyn RngCore) {
+        if let Some(i) = choose_weighted(rng, &self.weights) {
+            self.mutators[i].mutate(program, rng);
+        }
+    }
+}
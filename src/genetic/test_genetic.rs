@@ -0,0 +1,484 @@
+use super::crossover::*;
+use super::evolve::*;
+use super::generate::*;
+use super::mutate::*;
+use super::*;
+use crate::virtual_machine::{execute_with, FaultPolicy, MachineBuilder};
+use crate::*;
+
+#[test]
+fn test_generate_reproducible() {
+    let generator = Generator::new();
+    let a = generator.population(&mut seeded_rng(1), 50);
+    let b = generator.population(&mut seeded_rng(1), 50);
+    let c = generator.population(&mut seeded_rng(2), 50);
+    assert!(a == b, "The same seed gave different populations.");
+    assert!(a != c, "Different seeds gave the same population.");
+    for program in &a {
+        assert!(
+            !program.is_empty() && program.len() <= 32,
+            "Generated a program of length {}.",
+            program.len()
+        );
+        assert!(!program.contains(&Instruction::Illegal));
+    }
+}
+
+#[test]
+fn test_generate_configured() {
+    let generator = Generator::new()
+        .length_distribution(LengthDistribution::Fixed(20))
+        .only_opcodes(&[Opcode::Add, Opcode::Move, Opcode::Output])
+        .address_weights(AddressWeights {
+            reg_abs: 1,
+            mem_abs: 0,
+            mem_reg: 0,
+            literal: 1,
+            mem_reg_offset: 0,
+            mem_reg_indexed: 0,
+            mem_mem: 0,
+        })
+        .literals(LiteralDistribution::Choice(vec![3, 5]))
+        .registers(vec![Register::R6])
+        .end_with_halt(true);
+    let mut rng = seeded_rng(3);
+    for _ in 0..20 {
+        let program = generator.program(&mut rng);
+        assert!(program.len() == 20);
+        assert!(program[19] == Instruction::Halt);
+        for instruction in &program[..19] {
+            assert!(
+                [Opcode::Add, Opcode::Move, Opcode::Output].contains(&instruction.opcode()),
+                "Generated {}",
+                instruction
+            );
+            for operand in instruction.operands() {
+                assert!(
+                    operand == Address::RegAbs(Register::R6)
+                        || operand == Address::Literal(3)
+                        || operand == Address::Literal(5),
+                    "Generated operand {}",
+                    operand
+                );
+            }
+        }
+        // Under protected semantics, such a program always runs to its Halt.
+        let config = MachineBuilder::new(128).fault_policy(FaultPolicy::protected());
+        let (outcome, _, _) = execute_with(&config, program, vec![], Some(100));
+        assert!(
+            outcome == virtual_machine::Outcome::Halt,
+            "Got {:?}",
+            outcome
+        );
+    }
+}
+
+#[test]
+fn test_generate_empty_programs() {
+    let mut rng = seeded_rng(14);
+    let empty = Generator::new().length_distribution(LengthDistribution::Fixed(0));
+    assert!(empty.program(&mut rng).is_empty());
+    // Even a program with no room for anything else ends with a Halt.
+    let halting = empty.end_with_halt(true);
+    assert!(halting.program(&mut rng) == vec![Instruction::Halt]);
+    for program in Generator::new()
+        .length(0, 2)
+        .end_with_halt(true)
+        .population(&mut rng, 30)
+    {
+        assert!(
+            program.last() == Some(&Instruction::Halt),
+            "Got {:?}",
+            program
+        );
+    }
+}
+
+#[test]
+fn test_generate_extreme_offsets() {
+    let generator = Generator::new()
+        .max_offset(i64::MIN)
+        .only_opcodes(&[Opcode::Push])
+        .address_weights(AddressWeights {
+            reg_abs: 0,
+            mem_abs: 0,
+            mem_reg: 0,
+            literal: 0,
+            mem_reg_offset: 1,
+            mem_reg_indexed: 0,
+            mem_mem: 0,
+        });
+    for program in generator.population(&mut seeded_rng(13), 20) {
+        for instruction in &program {
+            assert!(matches!(
+                instruction.operands()[0],
+                Address::MemRegOffset(_, o) if o != i64::MIN
+            ));
+        }
+    }
+}
+
+/// A program in which every instruction is different, so rearrangements are easy to spot.
+fn numbered(len: usize) -> Program {
+    (0..len as Word)
+        .map(|i| Instruction::Push(Address::Literal(i)))
+        .collect()
+}
+
+#[test]
+fn test_structural_mutations() {
+    let mut rng = seeded_rng(4);
+    let generator = Generator::new();
+    let sorted = |program: &Program| {
+        let mut v: Vec<Word> = program
+            .iter()
+            .map(|i| match i.operands()[0] {
+                Address::Literal(v) => v,
+                _ => Word::MAX,
+            })
+            .collect();
+        v.sort_unstable();
+        v
+    };
+    for _ in 0..50 {
+        let mut program = numbered(10);
+        Insertion::new(generator.clone()).mutate(&mut program, &mut rng);
+        assert!(program.len() == 11);
+
+        let mut program = numbered(10);
+        Deletion.mutate(&mut program, &mut rng);
+        assert!(program.len() == 9);
+
+        let mut program = numbered(10);
+        Duplication.mutate(&mut program, &mut rng);
+        assert!(program.len() == 11);
+        assert!(program.windows(2).any(|w| w[0] == w[1]));
+
+        // Swaps and inversions only rearrange instructions.
+        for mutator in &[
+            Box::new(BlockSwap::new(4)) as Box<dyn Mutator>,
+            Box::new(BlockInversion::new(4)),
+        ] {
+            let mut program = numbered(10);
+            mutator.mutate(&mut program, &mut rng);
+            assert!(program != numbered(10), "{:?} changed nothing", program);
+            assert!(sorted(&program) == sorted(&numbered(10)));
+        }
+    }
+    // Nothing to work on is fine.
+    let mut empty = Program::new();
+    Weighted::standard(&generator)
+        .repeat(100)
+        .mutate(&mut empty, &mut rng);
+}
+
+#[test]
+fn test_point_mutations() {
+    let mut rng = seeded_rng(5);
+    let generator = Generator::new().registers(vec![Register::R7]);
+    let original = vec![
+        Instruction::Add(Address::RegAbs(Register::R0), Address::Literal(100)),
+        Instruction::Move(
+            Address::MemRegIndexed(Register::R1, Register::R2),
+            Address::MemRegOffset(Register::R3, -1),
+        ),
+    ];
+    for _ in 0..50 {
+        let mut program = original.clone();
+        RegisterMutation::new(generator.clone()).mutate(&mut program, &mut rng);
+        let changed: Vec<_> = (0..2).filter(|&i| program[i] != original[i]).collect();
+        assert!(changed.len() == 1, "{:?}", program);
+        let i = changed[0];
+        assert!(program[i].opcode() == original[i].opcode());
+        assert!(format!("{}", program[i]).contains("R7"));
+
+        let mut program = original.clone();
+        LiteralMutation::new(generator.clone())
+            .step(5)
+            .mutate(&mut program, &mut rng);
+        match program[0] {
+            Instruction::Add(Address::RegAbs(Register::R0), Address::Literal(v)) => {
+                assert!((95..=105).contains(&v), "Stepped to {}", v)
+            }
+            other => panic!("Literal mutation produced {}", other),
+        }
+        assert!(program[1] == original[1]);
+
+        let mut program = original.clone();
+        OpcodeMutation::new(generator.clone()).mutate(&mut program, &mut rng);
+        assert!(program.len() == 2);
+    }
+
+    // The same seed gives the same mutations.
+    let mutator = Weighted::standard(&generator).repeat(20);
+    let mut a = original.clone();
+    let mut b = original.clone();
+    mutator.mutate(&mut a, &mut seeded_rng(6));
+    mutator.mutate(&mut b, &mut seeded_rng(6));
+    assert!(a == b);
+}
+
+/// A parent whose every instruction is tagged with a unique number in its last operand. Every
+/// third instruction jumps: absolutely to the instruction five later, or relatively to the
+/// one two earlier.
+fn tagged(base: Word, len: usize) -> Program {
+    (0..len)
+        .map(|i| {
+            let tag = Address::Literal(base + i as Word);
+            match i % 3 {
+                0 => Instruction::JumpIfZero(Address::Literal((i + 5) as Word), tag),
+                1 => Instruction::JumpRelNotZero(Address::Literal(-2i64 as Word), tag),
+                _ => Instruction::Push(tag),
+            }
+        })
+        .collect()
+}
+
+fn tag(instruction: &Instruction) -> Word {
+    match instruction.operands().last() {
+        Some(&Address::Literal(v)) => v,
+        _ => panic!("{} has no tag", instruction),
+    }
+}
+
+#[test]
+fn test_crossover_repairs_jumps() {
+    let a = tagged(1000, 20);
+    let b = tagged(2000, 13);
+    let operators: Vec<Box<dyn Crossover>> = vec![
+        Box::new(OnePoint::new().repair_jumps(true)),
+        Box::new(TwoPoint::new().repair_jumps(true)),
+        Box::new(Uniform::new().repair_jumps(true)),
+        Box::new(Homologous::new().repair_jumps(true)),
+    ];
+    let mut rng = seeded_rng(8);
+    for operator in &operators {
+        for _ in 0..50 {
+            let (first, second) = operator.cross(&a, &b, &mut rng);
+            assert!(first.len() + second.len() == a.len() + b.len());
+            for child in &[first, second] {
+                for (position, instruction) in child.iter().enumerate() {
+                    let t = tag(instruction);
+                    let parent = if t >= 2000 { &b } else { &a };
+                    let original = (t % 1000) as usize;
+                    let (target, offset) = match *instruction {
+                        Instruction::JumpIfZero(Address::Literal(target), _) => (target, 0),
+                        Instruction::JumpRelNotZero(Address::Literal(offset), _) => {
+                            (position as Word, offset)
+                        }
+                        _ => continue,
+                    };
+                    let old_target = match parent[original] {
+                        Instruction::JumpIfZero(Address::Literal(target), _) => target,
+                        _ => (original as Word).wrapping_sub(2),
+                    } as usize;
+                    let old_tag = match parent.get(old_target) {
+                        Some(i) => tag(i),
+                        None => continue,
+                    };
+                    // If the target made it into the child, the jump must still reach it.
+                    if let Some(new) = child.iter().position(|i| tag(i) == old_tag) {
+                        assert!(
+                            target.wrapping_add(offset) == new as Word,
+                            "{} at {} should target {}",
+                            instruction,
+                            position,
+                            new
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Homologous crossover keeps lengths; without repair, jumps are copied verbatim.
+    let (first, second) = Homologous::new().cross(&a, &b, &mut rng);
+    assert!(first.len() == a.len() && second.len() == b.len());
+    for (i, instruction) in first.iter().enumerate() {
+        assert!(*instruction == a[i] || *instruction == b[i]);
+    }
+}
+
+fn sum_cases() -> TestCases {
+    TestCases::new(vec![
+        (vec![1, 2], vec![3]),
+        (vec![5, 5], vec![10]),
+        (vec![7, 0], vec![7]),
+        (vec![0, 9], vec![9]),
+    ])
+}
+
+fn sum_generator() -> Generator {
+    Generator::new()
+        .length(2, 8)
+        .only_opcodes(&[Opcode::Input, Opcode::Add, Opcode::Output, Opcode::Halt])
+        .registers(vec![Register::R0, Register::R1])
+        .address_weights(AddressWeights {
+            reg_abs: 1,
+            mem_abs: 0,
+            mem_reg: 0,
+            literal: 0,
+            mem_reg_offset: 0,
+            mem_reg_indexed: 0,
+            mem_mem: 0,
+        })
+}
+
+#[test]
+fn test_test_cases_scores_all() {
+    let programs = sum_generator().population(&mut seeded_rng(12), 30);
+    let cases = sum_cases();
+    let one_by_one: Vec<_> = programs.iter().map(|p| cases.scores(p)).collect();
+    assert!(cases.scores_all(&programs) == one_by_one);
+    assert!(TestCases::new(vec![]).scores_all(&programs) == vec![Vec::<f64>::new(); 30]);
+}
+
+#[test]
+fn test_test_cases_config_keeps_threads() {
+    let config = MachineBuilder::new(64);
+    let threads_first = sum_cases().threads(2).config(config.clone());
+    let config_first = sum_cases().config(config).threads(2);
+    assert!(threads_first == config_first, "{:?}", threads_first);
+}
+
+/// Scores whole batches, and refuses to score programs one at a time.
+struct BatchOnly;
+
+impl Fitness for BatchOnly {
+    fn scores(&self, _program: &Program) -> Vec<f64> {
+        panic!("Scored a single program.");
+    }
+
+    fn scores_all(&self, programs: &[Program]) -> Vec<Vec<f64>> {
+        programs.iter().map(|p| vec![p.len() as f64]).collect()
+    }
+}
+
+#[test]
+fn test_evolver_scores_in_batches() {
+    for &mode in &[Mode::Generational, Mode::SteadyState] {
+        let mut evolver = Evolver::new(sum_generator(), BatchOnly)
+            .population_size(10)
+            .mode(mode);
+        evolver.run(3);
+        assert!(evolver.generation() == 3);
+    }
+}
+
+#[test]
+fn test_evolver_solves_sum() {
+    let selections = [
+        Selection::Tournament(3),
+        Selection::Roulette,
+        Selection::Truncation(0.3),
+        Selection::Lexicase,
+    ];
+    for &selection in &selections {
+        for &mode in &[Mode::Generational, Mode::SteadyState] {
+            let mut evolver = Evolver::new(sum_generator(), sum_cases())
+                .population_size(60)
+                .selection(selection)
+                .mode(mode)
+                .elitism(2)
+                .seed(11);
+            let mean = |e: &Evolver<TestCases>| {
+                e.population().iter().map(|i| i.fitness).sum::<f64>() / 60.0
+            };
+            evolver.step();
+            let initial = mean(&evolver);
+            let best = evolver.run(100).clone();
+            assert!(evolver.population().len() == 60);
+            assert!(
+                mean(&evolver) > initial,
+                "{:?} {:?} made no progress from a mean of {}",
+                selection,
+                mode,
+                initial
+            );
+            // Not every combination finds a perfect program with this seed, but these do.
+            if mode == Mode::Generational
+                && matches!(selection, Selection::Tournament(_) | Selection::Lexicase)
+            {
+                assert!(
+                    best.fitness == 0.0,
+                    "{:?} only reached {} with {:?}",
+                    selection,
+                    best.fitness,
+                    best.program
+                );
+                let (_, _, output) =
+                    virtual_machine::execute(best.program, vec![20, 22], Some(1000));
+                assert!(output.first() == Some(&42), "Got {:?}", output);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_evolver_population_size() {
+    for &mode in &[Mode::Generational, Mode::SteadyState] {
+        for &elitism in &[0, 3, 9, 10, 15] {
+            let mut evolver = Evolver::new(sum_generator(), sum_cases())
+                .population_size(10)
+                .elitism(elitism)
+                .mode(mode);
+            for _ in 0..5 {
+                evolver.step();
+                assert!(
+                    evolver.population().len() == 10,
+                    "{:?} with {} elite grew to {}",
+                    mode,
+                    elitism,
+                    evolver.population().len()
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_roulette_infinite_scores() {
+    for &extreme in &[f64::INFINITY, f64::NEG_INFINITY] {
+        let fitness = move |p: &Program| vec![if p.len() > 3 { extreme } else { 0.0 }];
+        let mut evolver = Evolver::new(sum_generator(), fitness)
+            .population_size(10)
+            .selection(Selection::Roulette)
+            .seed(5);
+        evolver.run(5);
+        assert!(
+            evolver.generation() == 5,
+            "Stopped with a score of {}",
+            extreme
+        );
+    }
+}
+
+#[test]
+fn test_evolver_reproducible() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    let run = |seed| {
+        let stats = Rc::new(RefCell::new(Vec::new()));
+        let recorded = stats.clone();
+        // A fitness which is never negative, and never solved, so every generation runs.
+        let mut evolver = Evolver::new(sum_generator(), |p: &Program| vec![p.len() as f64])
+            .population_size(20)
+            .seed(seed)
+            .on_generation(move |s| recorded.borrow_mut().push(s.clone()));
+        evolver.run(5);
+        assert!(evolver.generation() == 5);
+        let stats = stats.borrow().clone();
+        stats
+    };
+    let a = run(1);
+    assert!(a.len() == 6, "Got {} reports", a.len());
+    for (i, stats) in a.iter().enumerate() {
+        assert!(stats.generation == i);
+        assert!(stats.best_fitness >= stats.mean_fitness);
+        assert!(stats.mean_fitness >= stats.worst_fitness);
+    }
+    // With an elite, the best never gets worse.
+    assert!(a.windows(2).all(|w| w[1].best_fitness >= w[0].best_fitness));
+    assert!(a == run(1));
+    assert!(a != run(2));
+}
@@ -1 +1,605 @@
+//! Metadata about the instruction set: opcodes, mnemonics and operand counts.
+//!
+//! Anything that needs to take an `Instruction` apart and put it back together
+//! (the assembler, for instance) goes through these tables rather than
+//! matching on every variant itself.
+use crate::*;
 
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+/// The operation an `Instruction` performs, without any of its operands.
+pub enum Opcode {
+    NoOp,
+    Zero,
+    Move,
+    Output,
+    Input,
+    OutputTo,
+    InputFrom,
+    InputRemaining,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    IMul,
+    IDiv,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+    Sar,
+    Rotl,
+    Rotr,
+    Neg,
+    Inc,
+    Dec,
+    Jump,
+    JumpIfZero,
+    JumpNotZero,
+    JumpRel,
+    JumpRelIfZero,
+    JumpRelNotZero,
+    JumpEq,
+    JumpNe,
+    JumpLt,
+    JumpGt,
+    JumpLe,
+    JumpGe,
+    JumpILt,
+    JumpIGt,
+    JumpILe,
+    JumpIGe,
+    SetEq,
+    SetNe,
+    SetLt,
+    SetGt,
+    SetLe,
+    SetGe,
+    SetILt,
+    SetIGt,
+    SetILe,
+    SetIGe,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FSqrt,
+    FSin,
+    FExp,
+    FLog,
+    IntToFloat,
+    FloatToInt,
+    FSetEq,
+    FSetLt,
+    FSetLe,
+    FSetGt,
+    FSetGe,
+    Call,
+    Ret,
+    AddWithCarry,
+    SubWithBorrow,
+    JumpFlags,
+    JumpNoFlags,
+    Push,
+    Pop,
+    Halt,
+    Illegal,
+}
+
+impl Opcode {
+    /// Every opcode, in declaration order.
+    pub const ALL: &'static [Opcode] = &[
+        Opcode::NoOp,
+        Opcode::Zero,
+        Opcode::Move,
+        Opcode::Output,
+        Opcode::Input,
+        Opcode::OutputTo,
+        Opcode::InputFrom,
+        Opcode::InputRemaining,
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::Mul,
+        Opcode::Div,
+        Opcode::Mod,
+        Opcode::IMul,
+        Opcode::IDiv,
+        Opcode::And,
+        Opcode::Or,
+        Opcode::Xor,
+        Opcode::Not,
+        Opcode::Shl,
+        Opcode::Shr,
+        Opcode::Sar,
+        Opcode::Rotl,
+        Opcode::Rotr,
+        Opcode::Neg,
+        Opcode::Inc,
+        Opcode::Dec,
+        Opcode::Jump,
+        Opcode::JumpIfZero,
+        Opcode::JumpNotZero,
+        Opcode::JumpRel,
+        Opcode::JumpRelIfZero,
+        Opcode::JumpRelNotZero,
+        Opcode::JumpEq,
+        Opcode::JumpNe,
+        Opcode::JumpLt,
+        Opcode::JumpGt,
+        Opcode::JumpLe,
+        Opcode::JumpGe,
+        Opcode::JumpILt,
+        Opcode::JumpIGt,
+        Opcode::JumpILe,
+        Opcode::JumpIGe,
+        Opcode::SetEq,
+        Opcode::SetNe,
+        Opcode::SetLt,
+        Opcode::SetGt,
+        Opcode::SetLe,
+        Opcode::SetGe,
+        Opcode::SetILt,
+        Opcode::SetIGt,
+        Opcode::SetILe,
+        Opcode::SetIGe,
+        Opcode::FAdd,
+        Opcode::FSub,
+        Opcode::FMul,
+        Opcode::FDiv,
+        Opcode::FSqrt,
+        Opcode::FSin,
+        Opcode::FExp,
+        Opcode::FLog,
+        Opcode::IntToFloat,
+        Opcode::FloatToInt,
+        Opcode::FSetEq,
+        Opcode::FSetLt,
+        Opcode::FSetLe,
+        Opcode::FSetGt,
+        Opcode::FSetGe,
+        Opcode::Call,
+        Opcode::Ret,
+        Opcode::AddWithCarry,
+        Opcode::SubWithBorrow,
+        Opcode::JumpFlags,
+        Opcode::JumpNoFlags,
+        Opcode::Push,
+        Opcode::Pop,
+        Opcode::Halt,
+        Opcode::Illegal,
+    ];
+
+    /// The assembly mnemonic for this opcode.
+    pub fn mnemonic(self) -> &'static str {
+        use self::Opcode::*;
+        match self {
+            NoOp => "noop",
+            Zero => "zero",
+            Move => "move",
+            Output => "output",
+            Input => "input",
+            OutputTo => "out",
+            InputFrom => "in",
+            InputRemaining => "remaining",
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+            Div => "div",
+            Mod => "mod",
+            IMul => "imul",
+            IDiv => "idiv",
+            And => "and",
+            Or => "or",
+            Xor => "xor",
+            Not => "not",
+            Shl => "shl",
+            Shr => "shr",
+            Sar => "sar",
+            Rotl => "rotl",
+            Rotr => "rotr",
+            Neg => "neg",
+            Inc => "inc",
+            Dec => "dec",
+            Jump => "jump",
+            JumpIfZero => "jz",
+            JumpNotZero => "jnz",
+            JumpRel => "jrel",
+            JumpRelIfZero => "jrz",
+            JumpRelNotZero => "jrnz",
+            JumpEq => "jeq",
+            JumpNe => "jne",
+            JumpLt => "jlt",
+            JumpGt => "jgt",
+            JumpLe => "jle",
+            JumpGe => "jge",
+            JumpILt => "jilt",
+            JumpIGt => "jigt",
+            JumpILe => "jile",
+            JumpIGe => "jige",
+            SetEq => "seq",
+            SetNe => "sne",
+            SetLt => "slt",
+            SetGt => "sgt",
+            SetLe => "sle",
+            SetGe => "sge",
+            SetILt => "silt",
+            SetIGt => "sigt",
+            SetILe => "sile",
+            SetIGe => "sige",
+            FAdd => "fadd",
+            FSub => "fsub",
+            FMul => "fmul",
+            FDiv => "fdiv",
+            FSqrt => "fsqrt",
+            FSin => "fsin",
+            FExp => "fexp",
+            FLog => "flog",
+            IntToFloat => "itof",
+            FloatToInt => "ftoi",
+            FSetEq => "fseq",
+            FSetLt => "fslt",
+            FSetLe => "fsle",
+            FSetGt => "fsgt",
+            FSetGe => "fsge",
+            Call => "call",
+            Ret => "ret",
+            AddWithCarry => "adc",
+            SubWithBorrow => "sbb",
+            JumpFlags => "jf",
+            JumpNoFlags => "jnf",
+            Push => "push",
+            Pop => "pop",
+            Halt => "halt",
+            Illegal => "illegal",
+        }
+    }
+
+    /// Look up an opcode by its mnemonic, ignoring case.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        Opcode::ALL
+            .iter()
+            .cloned()
+            .find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+
+    /// The number of `Address` operands an instruction with this opcode takes.
+    pub fn arity(self) -> usize {
+        use self::Opcode::*;
+        match self {
+            NoOp | Halt | Illegal | Ret => 0,
+            Zero | Output | Input | Jump | JumpRel | Call | Push | Pop | Not | Neg | Inc | Dec
+            | FSqrt | FSin | FExp | FLog | IntToFloat | FloatToInt => 1,
+            Move | OutputTo | InputFrom | InputRemaining | Add | Sub | Mul | Div | Mod | IMul
+            | IDiv | And | Or | Xor | Shl | Shr | Sar | Rotl | Rotr | JumpIfZero | JumpNotZero
+            | JumpRelIfZero | JumpRelNotZero | AddWithCarry | SubWithBorrow | JumpFlags
+            | JumpNoFlags | SetEq | SetNe | SetLt | SetGt | SetLe | SetGe | SetILt | SetIGt
+            | SetILe | SetIGe | FAdd | FSub | FMul | FDiv | FSetEq | FSetLt | FSetLe | FSetGt
+            | FSetGe => 2,
+            JumpEq | JumpNe | JumpLt | JumpGt | JumpLe | JumpGe | JumpILt | JumpIGt | JumpILe
+            | JumpIGe => 3,
+        }
+    }
+
+    /// Whether this opcode's first operand is an offset relative to the instruction's own
+    /// position, rather than an absolute instruction index.
+    pub fn is_relative_jump(self) -> bool {
+        use self::Opcode::*;
+        matches!(self, JumpRel | JumpRelIfZero | JumpRelNotZero)
+    }
+
+    /// Whether this opcode's first operand is the absolute index of an instruction to jump
+    /// to (or call).
+    pub fn is_absolute_jump(self) -> bool {
+        use self::Opcode::*;
+        matches!(
+            self,
+            Jump | JumpIfZero
+                | JumpNotZero
+                | JumpEq
+                | JumpNe
+                | JumpLt
+                | JumpGt
+                | JumpLe
+                | JumpGe
+                | JumpILt
+                | JumpIGt
+                | JumpILe
+                | JumpIGe
+                | JumpFlags
+                | JumpNoFlags
+                | Call
+        )
+    }
+}
+
+impl Instruction {
+    /// The opcode of this instruction.
+    pub fn opcode(&self) -> Opcode {
+        use self::Instruction::*;
+        match *self {
+            NoOp => Opcode::NoOp,
+            Zero(_) => Opcode::Zero,
+            Move(_, _) => Opcode::Move,
+            Output(_) => Opcode::Output,
+            Input(_) => Opcode::Input,
+            OutputTo(_, _) => Opcode::OutputTo,
+            InputFrom(_, _) => Opcode::InputFrom,
+            InputRemaining(_, _) => Opcode::InputRemaining,
+            Add(_, _) => Opcode::Add,
+            Sub(_, _) => Opcode::Sub,
+            Mul(_, _) => Opcode::Mul,
+            Div(_, _) => Opcode::Div,
+            Mod(_, _) => Opcode::Mod,
+            IMul(_, _) => Opcode::IMul,
+            IDiv(_, _) => Opcode::IDiv,
+            And(_, _) => Opcode::And,
+            Or(_, _) => Opcode::Or,
+            Xor(_, _) => Opcode::Xor,
+            Not(_) => Opcode::Not,
+            Shl(_, _) => Opcode::Shl,
+            Shr(_, _) => Opcode::Shr,
+            Sar(_, _) => Opcode::Sar,
+            Rotl(_, _) => Opcode::Rotl,
+            Rotr(_, _) => Opcode::Rotr,
+            Neg(_) => Opcode::Neg,
+            Inc(_) => Opcode::Inc,
+            Dec(_) => Opcode::Dec,
+            Jump(_) => Opcode::Jump,
+            JumpIfZero(_, _) => Opcode::JumpIfZero,
+            JumpNotZero(_, _) => Opcode::JumpNotZero,
+            JumpRel(_) => Opcode::JumpRel,
+            JumpRelIfZero(_, _) => Opcode::JumpRelIfZero,
+            JumpRelNotZero(_, _) => Opcode::JumpRelNotZero,
+            JumpEq(_, _, _) => Opcode::JumpEq,
+            JumpNe(_, _, _) => Opcode::JumpNe,
+            JumpLt(_, _, _) => Opcode::JumpLt,
+            JumpGt(_, _, _) => Opcode::JumpGt,
+            JumpLe(_, _, _) => Opcode::JumpLe,
+            JumpGe(_, _, _) => Opcode::JumpGe,
+            JumpILt(_, _, _) => Opcode::JumpILt,
+            JumpIGt(_, _, _) => Opcode::JumpIGt,
+            JumpILe(_, _, _) => Opcode::JumpILe,
+            JumpIGe(_, _, _) => Opcode::JumpIGe,
+            SetEq(_, _) => Opcode::SetEq,
+            SetNe(_, _) => Opcode::SetNe,
+            SetLt(_, _) => Opcode::SetLt,
+            SetGt(_, _) => Opcode::SetGt,
+            SetLe(_, _) => Opcode::SetLe,
+            SetGe(_, _) => Opcode::SetGe,
+            SetILt(_, _) => Opcode::SetILt,
+            SetIGt(_, _) => Opcode::SetIGt,
+            SetILe(_, _) => Opcode::SetILe,
+            SetIGe(_, _) => Opcode::SetIGe,
+            FAdd(_, _) => Opcode::FAdd,
+            FSub(_, _) => Opcode::FSub,
+            FMul(_, _) => Opcode::FMul,
+            FDiv(_, _) => Opcode::FDiv,
+            FSqrt(_) => Opcode::FSqrt,
+            FSin(_) => Opcode::FSin,
+            FExp(_) => Opcode::FExp,
+            FLog(_) => Opcode::FLog,
+            IntToFloat(_) => Opcode::IntToFloat,
+            FloatToInt(_) => Opcode::FloatToInt,
+            FSetEq(_, _) => Opcode::FSetEq,
+            FSetLt(_, _) => Opcode::FSetLt,
+            FSetLe(_, _) => Opcode::FSetLe,
+            FSetGt(_, _) => Opcode::FSetGt,
+            FSetGe(_, _) => Opcode::FSetGe,
+            Call(_) => Opcode::Call,
+            Ret => Opcode::Ret,
+            AddWithCarry(_, _) => Opcode::AddWithCarry,
+            SubWithBorrow(_, _) => Opcode::SubWithBorrow,
+            JumpFlags(_, _) => Opcode::JumpFlags,
+            JumpNoFlags(_, _) => Opcode::JumpNoFlags,
+            Push(_) => Opcode::Push,
+            Pop(_) => Opcode::Pop,
+            Halt => Opcode::Halt,
+            Illegal => Opcode::Illegal,
+        }
+    }
+
+    /// The operands of this instruction, in order (a, b, ...).
+    pub fn operands(&self) -> Vec<Address> {
+        use self::Instruction::*;
+        match *self {
+            NoOp | Halt | Illegal | Ret => vec![],
+            Zero(a) | Output(a) | Input(a) | Jump(a) | JumpRel(a) | Call(a) | Push(a) | Pop(a)
+            | Not(a) | Neg(a) | Inc(a) | Dec(a) | FSqrt(a) | FSin(a) | FExp(a) | FLog(a)
+            | IntToFloat(a) | FloatToInt(a) => vec![a],
+            Move(a, b)
+            | OutputTo(a, b)
+            | InputFrom(a, b)
+            | InputRemaining(a, b)
+            | Add(a, b)
+            | Sub(a, b)
+            | Mul(a, b)
+            | Div(a, b)
+            | Mod(a, b)
+            | IMul(a, b)
+            | IDiv(a, b)
+            | And(a, b)
+            | Or(a, b)
+            | Xor(a, b)
+            | Shl(a, b)
+            | Shr(a, b)
+            | Sar(a, b)
+            | Rotl(a, b)
+            | Rotr(a, b)
+            | JumpIfZero(a, b)
+            | JumpNotZero(a, b)
+            | JumpRelIfZero(a, b)
+            | JumpRelNotZero(a, b)
+            | AddWithCarry(a, b)
+            | SubWithBorrow(a, b)
+            | JumpFlags(a, b)
+            | JumpNoFlags(a, b)
+            | SetEq(a, b)
+            | SetNe(a, b)
+            | SetLt(a, b)
+            | SetGt(a, b)
+            | SetLe(a, b)
+            | SetGe(a, b)
+            | SetILt(a, b)
+            | SetIGt(a, b)
+            | SetILe(a, b)
+            | SetIGe(a, b)
+            | FAdd(a, b)
+            | FSub(a, b)
+            | FMul(a, b)
+            | FDiv(a, b)
+            | FSetEq(a, b)
+            | FSetLt(a, b)
+            | FSetLe(a, b)
+            | FSetGt(a, b)
+            | FSetGe(a, b) => vec![a, b],
+            JumpEq(a, b, c)
+            | JumpNe(a, b, c)
+            | JumpLt(a, b, c)
+            | JumpGt(a, b, c)
+            | JumpLe(a, b, c)
+            | JumpGe(a, b, c)
+            | JumpILt(a, b, c)
+            | JumpIGt(a, b, c)
+            | JumpILe(a, b, c)
+            | JumpIGe(a, b, c) => vec![a, b, c],
+        }
+    }
+
+    /// Build an instruction from an opcode and its operands.
+    /// Returns `None` if the number of operands doesn't match the opcode's arity.
+    pub fn from_parts(opcode: Opcode, operands: &[Address]) -> Option<Instruction> {
+        use self::Instruction::*;
+        if operands.len() != opcode.arity() {
+            return None;
+        }
+        let a = operands.first().cloned();
+        let b = operands.get(1).cloned();
+        let c = operands.get(2).cloned();
+        Some(match opcode {
+            Opcode::NoOp => NoOp,
+            Opcode::Zero => Zero(a?),
+            Opcode::Move => Move(a?, b?),
+            Opcode::Output => Output(a?),
+            Opcode::Input => Input(a?),
+            Opcode::OutputTo => OutputTo(a?, b?),
+            Opcode::InputFrom => InputFrom(a?, b?),
+            Opcode::InputRemaining => InputRemaining(a?, b?),
+            Opcode::Add => Add(a?, b?),
+            Opcode::Sub => Sub(a?, b?),
+            Opcode::Mul => Mul(a?, b?),
+            Opcode::Div => Div(a?, b?),
+            Opcode::Mod => Mod(a?, b?),
+            Opcode::IMul => IMul(a?, b?),
+            Opcode::IDiv => IDiv(a?, b?),
+            Opcode::And => And(a?, b?),
+            Opcode::Or => Or(a?, b?),
+            Opcode::Xor => Xor(a?, b?),
+            Opcode::Not => Not(a?),
+            Opcode::Shl => Shl(a?, b?),
+            Opcode::Shr => Shr(a?, b?),
+            Opcode::Sar => Sar(a?, b?),
+            Opcode::Rotl => Rotl(a?, b?),
+            Opcode::Rotr => Rotr(a?, b?),
+            Opcode::Neg => Neg(a?),
+            Opcode::Inc => Inc(a?),
+            Opcode::Dec => Dec(a?),
+            Opcode::Jump => Jump(a?),
+            Opcode::JumpIfZero => JumpIfZero(a?, b?),
+            Opcode::JumpNotZero => JumpNotZero(a?, b?),
+            Opcode::JumpRel => JumpRel(a?),
+            Opcode::JumpRelIfZero => JumpRelIfZero(a?, b?),
+            Opcode::JumpRelNotZero => JumpRelNotZero(a?, b?),
+            Opcode::JumpEq => JumpEq(a?, b?, c?),
+            Opcode::JumpNe => JumpNe(a?, b?, c?),
+            Opcode::JumpLt => JumpLt(a?, b?, c?),
+            Opcode::JumpGt => JumpGt(a?, b?, c?),
+            Opcode::JumpLe => JumpLe(a?, b?, c?),
+            Opcode::JumpGe => JumpGe(a?, b?, c?),
+            Opcode::JumpILt => JumpILt(a?, b?, c?),
+            Opcode::JumpIGt => JumpIGt(a?, b?, c?),
+            Opcode::JumpILe => JumpILe(a?, b?, c?),
+            Opcode::JumpIGe => JumpIGe(a?, b?, c?),
+            Opcode::SetEq => SetEq(a?, b?),
+            Opcode::SetNe => SetNe(a?, b?),
+            Opcode::SetLt => SetLt(a?, b?),
+            Opcode::SetGt => SetGt(a?, b?),
+            Opcode::SetLe => SetLe(a?, b?),
+            Opcode::SetGe => SetGe(a?, b?),
+            Opcode::SetILt => SetILt(a?, b?),
+            Opcode::SetIGt => SetIGt(a?, b?),
+            Opcode::SetILe => SetILe(a?, b?),
+            Opcode::SetIGe => SetIGe(a?, b?),
+            Opcode::FAdd => FAdd(a?, b?),
+            Opcode::FSub => FSub(a?, b?),
+            Opcode::FMul => FMul(a?, b?),
+            Opcode::FDiv => FDiv(a?, b?),
+            Opcode::FSqrt => FSqrt(a?),
+            Opcode::FSin => FSin(a?),
+            Opcode::FExp => FExp(a?),
+            Opcode::FLog => FLog(a?),
+            Opcode::IntToFloat => IntToFloat(a?),
+            Opcode::FloatToInt => FloatToInt(a?),
+            Opcode::FSetEq => FSetEq(a?, b?),
+            Opcode::FSetLt => FSetLt(a?, b?),
+            Opcode::FSetLe => FSetLe(a?, b?),
+            Opcode::FSetGt => FSetGt(a?, b?),
+            Opcode::FSetGe => FSetGe(a?, b?),
+            Opcode::Call => Call(a?),
+            Opcode::Ret => Ret,
+            Opcode::AddWithCarry => AddWithCarry(a?, b?),
+            Opcode::SubWithBorrow => SubWithBorrow(a?, b?),
+            Opcode::JumpFlags => JumpFlags(a?, b?),
+            Opcode::JumpNoFlags => JumpNoFlags(a?, b?),
+            Opcode::Push => Push(a?),
+            Opcode::Pop => Pop(a?),
+            Opcode::Halt => Halt,
+            Opcode::Illegal => Illegal,
+        })
+    }
+}
+
+impl Register {
+    /// Every register, in declaration order.
+    pub const ALL: &'static [Register] = &[
+        Register::R0,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+        Register::SP,
+        Register::BP,
+        Register::Flags,
+    ];
+
+    /// The assembly name of this register.
+    pub fn name(self) -> &'static str {
+        use self::Register::*;
+        match self {
+            R0 => "R0",
+            R1 => "R1",
+            R2 => "R2",
+            R3 => "R3",
+            R4 => "R4",
+            R5 => "R5",
+            R6 => "R6",
+            R7 => "R7",
+            SP => "SP",
+            BP => "BP",
+            Flags => "FLAGS",
+        }
+    }
+
+    /// Look up a register by its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Register> {
+        Register::ALL
+            .iter()
+            .cloned()
+            .find(|r| r.name().eq_ignore_ascii_case(name))
+    }
+}
@@ -3,9 +3,11 @@
 //! machine for evolutionary algorithms to program against.
 //!
 //! The machine has eight GPRs (`R0` through `R7`), a hardware stack with SP and BP,
+//! a status flags register set by arithmetic,
+//! floating-point instructions which treat words as IEEE-754 doubles,
 //! and hardware I/O with Input and Output.
 //!
-//! These I/O instructions write out whole `u64`s in big endian.
+//! These I/O instructions read and write whole `u64`s through `virtual_machine::IoPort`s.
 //!
 //! # Example
 //! ```
@@ -46,8 +48,21 @@ extern crate serde;
 #[cfg(feature = "serialize")]
 extern crate serde_derive;
 
+#[cfg(feature = "genetic")]
+extern crate rand;
+
+#[cfg(feature = "genetic")]
+extern crate rand_chacha;
+
+pub mod assembler;
+pub mod bytecode;
+pub mod debugger;
+#[cfg(feature = "genetic")]
+pub mod genetic;
+mod instructions;
 pub mod virtual_machine;
-//mod assembler;
+
+pub use crate::instructions::Opcode;
 
 /// Represents a machine word - an atomic int, a pointer, etc.
 /// Words are u64s; signed math has to do conversion.
@@ -62,15 +77,26 @@ pub type Program = Vec<Instruction>;
 
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 #[derive(PartialEq, Debug, Copy, Clone)]
-/// Represents a place a value can come from: a register, a memory address, a pointer to memory stored in a register, or a literal value.
+/// Represents a place a value can come from: a register, a memory address, a pointer to memory
+/// stored in a register or in memory, or a literal value.
+///
+/// Memory addresses computed from registers wrap around, so `MemRegOffset(BP, -1)` with BP at 0
+/// refers to address `u64::MAX`.
 pub enum Address {
     /// A literal register, like R1.
     RegAbs(Register),
     /// A literal memory address, like 0x10.
     MemAbs(Word),
-    /// A memory address stored in a register. This serves as one level of indirection;
-    /// for multiple indirection, multiple instructions must be used.
+    /// A memory address stored in a register. This serves as one level of indirection.
     MemReg(Register),
+    /// A memory address stored in a register, plus a signed displacement; handy for reaching
+    /// values in a stack frame relative to BP.
+    MemRegOffset(Register, i64),
+    /// A memory address found by adding together the values of two registers, a base and an
+    /// index.
+    MemRegIndexed(Register, Register),
+    /// A memory address stored in memory at the given address: a pointer to a pointer.
+    MemMem(Word),
     /// A literal value. Writing to a literal value is a fault.
     Literal(Word),
 }
@@ -102,6 +128,9 @@ pub enum Register {
     SP,
     /// Stack base pointer
     BP,
+    /// Status flags, set by arithmetic instructions. See `virtual_machine::FLAG_ZERO` and friends
+    /// for the meaning of each bit.
+    Flags,
 }
 
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
@@ -119,16 +148,148 @@ pub enum Instruction {
     Output(Address),
     /// Pop from the input into a
     Input(Address),
+    /// Write b to the output channel numbered a
+    OutputTo(Address, Address),
+    /// Read from the input channel numbered a into b
+    InputFrom(Address, Address),
+    /// Store in b the number of words left to read on the input channel numbered a
+    InputRemaining(Address, Address),
     /// Add the unsigned a to b, storing the result in a
     Add(Address, Address),
     /// Subtract the unsigned b from a, storing the result in a
     Sub(Address, Address),
+    /// Multiply the unsigned a by b, storing the result in a
+    Mul(Address, Address),
+    /// Divide the unsigned a by b, storing the quotient in a. Dividing by zero is a Fault.
+    Div(Address, Address),
+    /// Divide the unsigned a by b, storing the remainder in a. Dividing by zero is a Fault.
+    Mod(Address, Address),
+    /// Multiply the signed a by b, storing the result in a
+    IMul(Address, Address),
+    /// Divide the signed a by b, storing the quotient (rounded towards zero) in a.
+    /// Dividing by zero is a Fault.
+    IDiv(Address, Address),
+    /// Bitwise AND a with b, storing the result in a
+    And(Address, Address),
+    /// Bitwise OR a with b, storing the result in a
+    Or(Address, Address),
+    /// Bitwise XOR a with b, storing the result in a
+    Xor(Address, Address),
+    /// Bitwise NOT a, storing the result in a
+    Not(Address),
+    /// Shift a left by b bits, storing the result in a. Only the low six bits of b are used.
+    Shl(Address, Address),
+    /// Shift a right by b bits, filling with zeros, storing the result in a.
+    /// Only the low six bits of b are used.
+    Shr(Address, Address),
+    /// Shift a right by b bits, filling with copies of the sign bit, storing the result in a.
+    /// Only the low six bits of b are used.
+    Sar(Address, Address),
+    /// Rotate a left by b bits, storing the result in a
+    Rotl(Address, Address),
+    /// Rotate a right by b bits, storing the result in a
+    Rotr(Address, Address),
+    /// Negate the signed a, storing the result in a
+    Neg(Address),
+    /// Add one to a, storing the result in a
+    Inc(Address),
+    /// Subtract one from a, storing the result in a
+    Dec(Address),
     /// Uncontitionally jump to the position given by a
     Jump(Address),
     /// Jump to a if the value at b is 0
     JumpIfZero(Address, Address),
     /// Jump to a if the value at b is NOT zero
     JumpNotZero(Address, Address),
+    /// Unconditionally jump by the signed offset a, relative to this instruction
+    JumpRel(Address),
+    /// Jump by the signed offset a, relative to this instruction, if the value at b is 0
+    JumpRelIfZero(Address, Address),
+    /// Jump by the signed offset a, relative to this instruction, if the value at b is NOT zero
+    JumpRelNotZero(Address, Address),
+    /// Jump to a if b is equal to c
+    JumpEq(Address, Address, Address),
+    /// Jump to a if b is not equal to c
+    JumpNe(Address, Address, Address),
+    /// Jump to a if the unsigned b is less than c
+    JumpLt(Address, Address, Address),
+    /// Jump to a if the unsigned b is greater than c
+    JumpGt(Address, Address, Address),
+    /// Jump to a if the unsigned b is less than or equal to c
+    JumpLe(Address, Address, Address),
+    /// Jump to a if the unsigned b is greater than or equal to c
+    JumpGe(Address, Address, Address),
+    /// Jump to a if the signed b is less than c
+    JumpILt(Address, Address, Address),
+    /// Jump to a if the signed b is greater than c
+    JumpIGt(Address, Address, Address),
+    /// Jump to a if the signed b is less than or equal to c
+    JumpILe(Address, Address, Address),
+    /// Jump to a if the signed b is greater than or equal to c
+    JumpIGe(Address, Address, Address),
+    /// Set a to 1 if a is equal to b, or to 0 otherwise
+    SetEq(Address, Address),
+    /// Set a to 1 if a is not equal to b, or to 0 otherwise
+    SetNe(Address, Address),
+    /// Set a to 1 if the unsigned a is less than b, or to 0 otherwise
+    SetLt(Address, Address),
+    /// Set a to 1 if the unsigned a is greater than b, or to 0 otherwise
+    SetGt(Address, Address),
+    /// Set a to 1 if the unsigned a is less than or equal to b, or to 0 otherwise
+    SetLe(Address, Address),
+    /// Set a to 1 if the unsigned a is greater than or equal to b, or to 0 otherwise
+    SetGe(Address, Address),
+    /// Set a to 1 if the signed a is less than b, or to 0 otherwise
+    SetILt(Address, Address),
+    /// Set a to 1 if the signed a is greater than b, or to 0 otherwise
+    SetIGt(Address, Address),
+    /// Set a to 1 if the signed a is less than or equal to b, or to 0 otherwise
+    SetILe(Address, Address),
+    /// Set a to 1 if the signed a is greater than or equal to b, or to 0 otherwise
+    SetIGe(Address, Address),
+    /// Add the floats a and b, storing the result in a
+    FAdd(Address, Address),
+    /// Subtract the float b from a, storing the result in a
+    FSub(Address, Address),
+    /// Multiply the floats a and b, storing the result in a
+    FMul(Address, Address),
+    /// Divide the float a by b, storing the result in a
+    FDiv(Address, Address),
+    /// Take the square root of the float a, storing the result in a
+    FSqrt(Address),
+    /// Take the sine of the float a (in radians), storing the result in a
+    FSin(Address),
+    /// Raise e to the power of the float a, storing the result in a
+    FExp(Address),
+    /// Take the natural logarithm of the float a, storing the result in a
+    FLog(Address),
+    /// Convert the signed integer a to the nearest float, storing the result in a
+    IntToFloat(Address),
+    /// Convert the float a to a signed integer, rounding towards zero and saturating,
+    /// storing the result in a
+    FloatToInt(Address),
+    /// Set a to 1 if the float a is equal to b, or to 0 otherwise
+    FSetEq(Address, Address),
+    /// Set a to 1 if the float a is less than b, or to 0 otherwise
+    FSetLt(Address, Address),
+    /// Set a to 1 if the float a is less than or equal to b, or to 0 otherwise
+    FSetLe(Address, Address),
+    /// Set a to 1 if the float a is greater than b, or to 0 otherwise
+    FSetGt(Address, Address),
+    /// Set a to 1 if the float a is greater than or equal to b, or to 0 otherwise
+    FSetGe(Address, Address),
+    /// Call the subroutine at a, saving the address of the next instruction to return to
+    Call(Address),
+    /// Return from a subroutine to the instruction after the matching Call
+    Ret,
+    /// Add b and the carry flag to a, storing the result in a
+    AddWithCarry(Address, Address),
+    /// Subtract b and the carry flag from a, storing the result in a
+    SubWithBorrow(Address, Address),
+    /// Jump to a if any of the flags in the mask b are set
+    JumpFlags(Address, Address),
+    /// Jump to a if none of the flags in the mask b are set
+    JumpNoFlags(Address, Address),
     /// Push a to the stack
     Push(Address),
     /// Pop a value from the stack into the given address
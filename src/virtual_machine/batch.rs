@@ -0,0 +1,189 @@
+//! Running many programs at once, across threads.
+use super::*;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// The machines a `BatchEvaluator` runs programs on.
+type BatchMachine = Machine<VecDeque<Word>, Vec<Word>>;
+
+/// The outcome, cycle count and output of a run, as `execute_with` gives them.
+type RunResult = (Outcome, u64, Vec<Word>);
+
+/// Runs batches of programs, each with its own input, on a set of machines which are kept
+/// from one batch to the next.
+///
+/// There is one machine per thread. Each is reset between runs rather than rebuilt, so its
+/// memory is only allocated once, however many programs it runs. The threads themselves only
+/// last as long as a call to `run`, which is what lets the programs and inputs be borrowed
+/// rather than copied for the workers.
+///
+/// # Example
+/// ```
+/// # use mlem::Instruction::*;
+/// # use mlem::Address::*;
+/// # use mlem::Register::*;
+/// # use mlem::virtual_machine::{BatchEvaluator, MachineBuilder, Outcome};
+/// let double = vec![Input(RegAbs(R0)), Add(RegAbs(R0), RegAbs(R0)), Output(RegAbs(R0)), Halt];
+/// let inputs: Vec<Vec<u64>> = (0..100).map(|i| vec![i]).collect();
+/// let runs: Vec<_> = inputs.iter().map(|i| (&double[..], &i[..])).collect();
+///
+/// let evaluator = BatchEvaluator::new(MachineBuilder::new(16));
+/// let results = evaluator.run(&runs, Some(10));
+/// for (i, (outcome, cycles, output)) in results.into_iter().enumerate() {
+///     assert_eq!(outcome, Outcome::Halt);
+///     assert_eq!(cycles, 3);
+///     assert_eq!(output, vec![2 * i as u64]);
+/// }
+/// ```
+pub struct BatchEvaluator {
+    config: MachineBuilder,
+    machines: Vec<Mutex<BatchMachine>>,
+}
+
+impl BatchEvaluator {
+    /// An evaluator with a machine built from the given configuration for each thread the
+    /// system can run in parallel.
+    pub fn new(config: MachineBuilder) -> Self {
+        Self::with_machines(config, available_threads())
+    }
+
+    /// Run batches on the given number of threads, at least one. With one thread, runs
+    /// happen on the calling thread.
+    pub fn threads(self, threads: usize) -> Self {
+        Self::with_machines(self.config, threads)
+    }
+
+    /// Build the machines from the given configuration instead, keeping the number of threads.
+    pub fn with_config(self, config: MachineBuilder) -> Self {
+        Self::with_machines(config, self.machines.len())
+    }
+
+    fn with_machines(config: MachineBuilder, threads: usize) -> Self {
+        let machines = (0..threads.max(1))
+            .map(|_| Mutex::new(config.build(VecDeque::new(), Vec::new())))
+            .collect();
+        BatchEvaluator { config, machines }
+    }
+
+    /// The configuration of every machine.
+    pub fn config(&self) -> &MachineBuilder {
+        &self.config
+    }
+
+    /// Run each program on its input, stopping each run after at most `limit` cycles, just
+    /// as `execute_with` would. Returns the outcome, cycle count and output of each run, in
+    /// the same order as the runs.
+    ///
+    /// If another thread is running a batch on the same evaluator, this waits for it.
+    pub fn run(
+        &self,
+        runs: &[(&[Instruction], &[Word])],
+        limit: Option<u64>,
+    ) -> Vec<(Outcome, u64, Vec<Word>)> {
+        // Machines are reset before every run, so one left behind by a panic is still usable.
+        let mut machines: Vec<MutexGuard<BatchMachine>> = self
+            .machines
+            .iter()
+            .map(|m| m.lock().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+        machines.truncate(runs.len().max(1));
+        let next = AtomicUsize::new(0);
+        let work = |m: &mut BatchMachine| run_shared(m, &self.config, runs, &next, limit);
+        if machines.len() == 1 {
+            return work(&mut machines[0])
+                .into_iter()
+                .map(|(_, result)| result)
+                .collect();
+        }
+
+        let mut results = vec![None; runs.len()];
+        thread::scope(|s| {
+            let workers: Vec<_> = machines
+                .iter_mut()
+                .map(|m| {
+                    let m: &mut BatchMachine = m;
+                    s.spawn(move || work(m))
+                })
+                .collect();
+            for worker in workers {
+                // A panic in a worker is passed on to the caller.
+                for (i, result) in worker.join().unwrap() {
+                    results[i] = Some(result);
+                }
+            }
+        });
+        // Every run was taken by some worker.
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+/// Take the next run nobody has started yet, until there are none left, and give back the
+/// results of those runs along with their positions.
+fn run_shared(
+    m: &mut BatchMachine,
+    config: &MachineBuilder,
+    runs: &[(&[Instruction], &[Word])],
+    next: &AtomicUsize,
+    limit: Option<u64>,
+) -> Vec<(usize, RunResult)> {
+    let mut done = Vec::new();
+    loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let &(program, input) = match runs.get(i) {
+            Some(run) => run,
+            None => return done,
+        };
+        config.reset(m);
+        m.program.clear();
+        m.program.extend_from_slice(program);
+        m.inputs[0].clear();
+        m.inputs[0].extend(input);
+        let (outcome, cycles) = run_to_limit(m, limit);
+        done.push((i, (outcome, cycles, std::mem::take(&mut m.outputs[0]))));
+    }
+}
+
+impl Clone for BatchEvaluator {
+    /// An evaluator with the same configuration and number of threads, but its own machines.
+    fn clone(&self) -> Self {
+        Self::with_machines(self.config.clone(), self.machines.len())
+    }
+}
+
+impl PartialEq for BatchEvaluator {
+    /// Evaluators are equal if they run the same configuration on the same number of threads.
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config && self.machines.len() == other.machines.len()
+    }
+}
+
+impl fmt::Debug for BatchEvaluator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchEvaluator")
+            .field("config", &self.config)
+            .field("threads", &self.machines.len())
+            .finish()
+    }
+}
+
+/// Like `execute_with`, but for many runs at once, each a program and its input, spread
+/// across threads. Returns the outcome, cycle count and output of each run, in the same
+/// order as the runs.
+///
+/// This builds a `BatchEvaluator` for the one batch, so its machines are reused between the
+/// runs in it but not beyond; keep a `BatchEvaluator` to reuse them across batches.
+pub fn execute_batch(
+    config: &MachineBuilder,
+    runs: &[(&[Instruction], &[Word])],
+    limit: Option<u64>,
+) -> Vec<(Outcome, u64, Vec<Word>)> {
+    let threads = available_threads().min(runs.len());
+    BatchEvaluator::with_machines(config.clone(), threads).run(runs, limit)
+}
+
+/// The number of threads the system can run in parallel.
+fn available_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
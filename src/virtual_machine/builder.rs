@@ -0,0 +1,221 @@
+//! Configuring a Machine before it starts.
+use super::*;
+
+/// A reusable description of a Machine: how much memory it has, where its stack lives,
+/// what its registers and memory start out holding, and how it handles faults.
+///
+/// Because it holds no I/O, a builder can be cloned and used to build any number of
+/// identical machines.
+///
+/// # Example
+/// ```
+/// # use mlem::Instruction::*;
+/// # use mlem::Address::*;
+/// # use mlem::Register::*;
+/// # use mlem::virtual_machine::{MachineBuilder, FaultPolicy, Outcome, execute_with};
+/// let config = MachineBuilder::new(1024)
+///     .stack_base(512)
+///     .register(R1, 40)
+///     .memory(vec![0, 2])
+///     .fault_policy(FaultPolicy::protected());
+///
+/// let program = vec![Add(RegAbs(R1), MemAbs(1)), Output(RegAbs(R1)), Output(RegAbs(SP)), Halt];
+/// let (outcome, _, output) = execute_with(&config, program, vec![], Some(10));
+/// assert_eq!(outcome, Outcome::Halt);
+/// assert_eq!(output, vec![42, 512]);
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+pub struct MachineBuilder {
+    max_words: usize,
+    registers: [Word; 8],
+    sp: Option<Word>,
+    bp: Option<Word>,
+    flags: Word,
+    stack_limit: Word,
+    memory: Vec<Word>,
+    return_stack: ReturnStack,
+    max_call_depth: usize,
+    relative_jumps: RelativeJumpPolicy,
+    float_policy: FloatPolicy,
+    fault_policy: FaultPolicy,
+    end_of_input: EndOfInput,
+    stream_output: bool,
+}
+
+impl MachineBuilder {
+    /// Describe a machine with the given number of words of memory, configured just as
+    /// `Machine::new` would configure it.
+    pub fn new(max_words: usize) -> Self {
+        Self {
+            max_words,
+            registers: [0; 8],
+            sp: None,
+            bp: None,
+            flags: 0,
+            stack_limit: 0,
+            memory: Vec::new(),
+            return_stack: ReturnStack::default(),
+            max_call_depth: usize::MAX,
+            relative_jumps: RelativeJumpPolicy::default(),
+            float_policy: FloatPolicy::default(),
+            fault_policy: FaultPolicy::default(),
+            end_of_input: EndOfInput::default(),
+            stream_output: false,
+        }
+    }
+
+    /// Change the number of words of memory.
+    pub fn max_words(mut self, max_words: usize) -> Self {
+        self.max_words = max_words;
+        self
+    }
+
+    /// Start the stack at the given address, setting both SP and BP to it.
+    /// By default the stack starts at the top of memory.
+    pub fn stack_base(mut self, base: Word) -> Self {
+        self.sp = Some(base);
+        self.bp = Some(base);
+        self
+    }
+
+    /// Fault with a stack overflow rather than let SP go down to or past the given address.
+    /// The default is 0, so the stack may grow down to address 1.
+    pub fn stack_limit(mut self, limit: Word) -> Self {
+        self.stack_limit = limit;
+        self
+    }
+
+    /// Set the initial value of a register. Setting SP or BP overrides `stack_base`.
+    pub fn register(mut self, register: Register, value: Word) -> Self {
+        match register {
+            Register::SP => self.sp = Some(value),
+            Register::BP => self.bp = Some(value),
+            Register::Flags => self.flags = value,
+            // R0 to R7 are declared in order.
+            r => self.registers[r as usize] = value,
+        }
+        self
+    }
+
+    /// Set the initial contents of memory, starting at address 0.
+    pub fn memory(mut self, image: Vec<Word>) -> Self {
+        self.memory = image;
+        self
+    }
+
+    /// Choose where Call and Ret keep return addresses.
+    pub fn return_stack(mut self, return_stack: ReturnStack) -> Self {
+        self.return_stack = return_stack;
+        self
+    }
+
+    /// Limit how deeply calls may nest.
+    pub fn max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Choose what relative jumps do when their target lies outside the program.
+    pub fn relative_jump_policy(mut self, policy: RelativeJumpPolicy) -> Self {
+        self.relative_jumps = policy;
+        self
+    }
+
+    /// Choose what floating-point instructions do when their result is NaN or infinite.
+    pub fn float_policy(mut self, policy: FloatPolicy) -> Self {
+        self.float_policy = policy;
+        self
+    }
+
+    /// Choose how the machine reacts to each class of recoverable fault.
+    pub fn fault_policy(mut self, policy: FaultPolicy) -> Self {
+        self.fault_policy = policy;
+        self
+    }
+
+    /// Choose what happens when channel 0, the input the machine is built with, runs out.
+    pub fn end_of_input(mut self, policy: EndOfInput) -> Self {
+        self.end_of_input = policy;
+        self
+    }
+
+    /// Choose whether every output pauses the machine with `Outcome::Output`.
+    pub fn stream_output(mut self, stream: bool) -> Self {
+        self.stream_output = stream;
+        self
+    }
+
+    /// Put a machine back into the state a machine built from this configuration starts in,
+    /// reusing the memory it has already allocated. Its program, I/O ports and observer are
+    /// kept, though any input supplied with `provide_input` is dropped and every input channel
+    /// but 0 goes back to faulting when it runs dry.
+    pub fn reset<I: IoPort, O: IoPort, T: ExecutionObserver>(
+        &self,
+        machine: &mut Machine<I, O, T>,
+    ) {
+        let top = self.max_words.saturating_sub(1) as Word;
+        machine.max_words = self.max_words;
+        machine.registers = self.registers;
+        machine.sp = self.sp.unwrap_or(top);
+        machine.bp = self.bp.unwrap_or(top);
+        machine.flags = self.flags;
+        machine.stack_limit = self.stack_limit;
+        machine.ip = 0;
+        machine.memory.clear();
+        machine.memory.extend_from_slice(&self.memory);
+        machine.cycles = 0;
+        machine.return_stack = self.return_stack;
+        machine.call_stack.clear();
+        machine.call_depth = 0;
+        machine.max_call_depth = self.max_call_depth;
+        machine.relative_jumps = self.relative_jumps;
+        machine.float_policy = self.float_policy;
+        machine.fault_policy = self.fault_policy;
+        for (channel, policy) in machine.end_of_input.iter_mut().enumerate() {
+            *policy = if channel == 0 {
+                self.end_of_input
+            } else {
+                EndOfInput::default()
+            };
+        }
+        for provided in &mut machine.provided {
+            provided.clear();
+        }
+        machine.stream_output = self.stream_output;
+        machine.pending = None;
+    }
+
+    /// Build a machine connected to the given I/O ports. It has no program loaded yet.
+    pub fn build<I: IoPort, O: IoPort>(&self, input: I, output: O) -> Machine<I, O> {
+        // Both SP and BP start at the top of memory by default; the stack grows downwards.
+        let top = self.max_words.saturating_sub(1) as Word;
+        let mut memory = Vec::with_capacity(self.max_words);
+        memory.extend_from_slice(&self.memory);
+        Machine {
+            max_words: self.max_words,
+            registers: self.registers,
+            sp: self.sp.unwrap_or(top),
+            bp: self.bp.unwrap_or(top),
+            flags: self.flags,
+            stack_limit: self.stack_limit,
+            ip: 0,
+            memory,
+            program: vec![Instruction::Illegal],
+            cycles: 0,
+            return_stack: self.return_stack,
+            call_stack: Vec::new(),
+            call_depth: 0,
+            max_call_depth: self.max_call_depth,
+            relative_jumps: self.relative_jumps,
+            float_policy: self.float_policy,
+            fault_policy: self.fault_policy,
+            inputs: vec![input],
+            end_of_input: vec![self.end_of_input],
+            provided: vec![VecDeque::new()],
+            stream_output: self.stream_output,
+            pending: None,
+            outputs: vec![output],
+            observer: NoObserver,
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Word-level I/O for Machines.
+use crate::*;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+/// Somewhere a Machine can read words from or write words to.
+///
+/// Ports work on whole `Word`s, so there's no encoding or decoding between the machine
+/// and, say, a fitness function. A port needn't support both directions: by default,
+/// reading gives nothing and writing fails.
+///
+/// `VecDeque<Word>` is the usual port for both input (words are read from the front) and
+/// output (words are written to the back). `Vec<Word>` is a simpler output-only port.
+/// To connect a machine to a byte stream, wrap it in a `ByteReader` or `ByteWriter`.
+pub trait IoPort {
+    /// Read the next word, or `None` if there is no more input.
+    fn read_word(&mut self) -> Option<Word> {
+        None
+    }
+
+    /// Write a word.
+    fn write_word(&mut self, _word: Word) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "port does not support output",
+        ))
+    }
+
+    /// The number of words left to read, or `None` if the port can't tell.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl IoPort for VecDeque<Word> {
+    fn read_word(&mut self) -> Option<Word> {
+        self.pop_front()
+    }
+
+    fn write_word(&mut self, word: Word) -> io::Result<()> {
+        self.push_back(word);
+        Ok(())
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl IoPort for Vec<Word> {
+    fn write_word(&mut self, word: Word) -> io::Result<()> {
+        self.push(word);
+        Ok(())
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+impl<P: IoPort + ?Sized> IoPort for &mut P {
+    fn read_word(&mut self) -> Option<Word> {
+        (**self).read_word()
+    }
+
+    fn write_word(&mut self, word: Word) -> io::Result<()> {
+        (**self).write_word(word)
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        (**self).remaining()
+    }
+}
+
+impl<P: IoPort + ?Sized> IoPort for Box<P> {
+    fn read_word(&mut self) -> Option<Word> {
+        (**self).read_word()
+    }
+
+    fn write_word(&mut self, word: Word) -> io::Result<()> {
+        (**self).write_word(word)
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        (**self).remaining()
+    }
+}
+
+/// The byte order words are encoded in on a byte stream.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub enum Endianness {
+    /// Most significant byte first. This is the default.
+    #[default]
+    Big,
+    /// Least significant byte first.
+    Little,
+}
+
+/// An input port reading words from a byte stream, eight bytes at a time.
+#[derive(Debug)]
+pub struct ByteReader<R: Read> {
+    inner: R,
+    endianness: Endianness,
+}
+
+impl<R: Read> ByteReader<R> {
+    /// Read big endian words from the given stream.
+    pub fn new(inner: R) -> Self {
+        Self::with_endianness(inner, Endianness::Big)
+    }
+
+    /// Read words in the given byte order from the given stream.
+    pub fn with_endianness(inner: R, endianness: Endianness) -> Self {
+        Self { inner, endianness }
+    }
+
+    /// Borrow the underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Mutably borrow the underlying stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Give back the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> IoPort for ByteReader<R> {
+    fn read_word(&mut self) -> Option<Word> {
+        // Whatever the reason a read fails, there's no more input to be had.
+        match self.endianness {
+            Endianness::Big => self.inner.read_u64::<BigEndian>().ok(),
+            Endianness::Little => self.inner.read_u64::<LittleEndian>().ok(),
+        }
+    }
+}
+
+/// An output port writing words to a byte stream, eight bytes at a time.
+#[derive(Debug)]
+pub struct ByteWriter<W: Write> {
+    inner: W,
+    endianness: Endianness,
+}
+
+impl<W: Write> ByteWriter<W> {
+    /// Write big endian words to the given stream.
+    pub fn new(inner: W) -> Self {
+        Self::with_endianness(inner, Endianness::Big)
+    }
+
+    /// Write words in the given byte order to the given stream.
+    pub fn with_endianness(inner: W, endianness: Endianness) -> Self {
+        Self { inner, endianness }
+    }
+
+    /// Borrow the underlying stream.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Mutably borrow the underlying stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Give back the underlying stream.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> IoPort for ByteWriter<W> {
+    fn write_word(&mut self, word: Word) -> io::Result<()> {
+        match self.endianness {
+            Endianness::Big => self.inner.write_u64::<BigEndian>(word),
+            Endianness::Little => self.inner.write_u64::<LittleEndian>(word),
+        }
+    }
+}
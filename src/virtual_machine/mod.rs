@@ -1,10 +1,21 @@
 //! A virtual machine capable of executing MLeM in-memory representation.
 use crate::*;
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{Read, Write};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+mod batch;
+mod builder;
+mod io_port;
+mod observer;
 #[cfg(test)]
 mod test_machine;
 
+pub use self::batch::{execute_batch, BatchEvaluator};
+pub use self::builder::MachineBuilder;
+pub use self::io_port::{ByteReader, ByteWriter, Endianness, IoPort};
+pub use self::observer::{ExecutionObserver, IoEvent, NoObserver};
+
 /// Represents the outcome of a program run;
 /// a halt (graceful termination) or a
 /// fault (hardware error), or a state of continuation,
@@ -15,18 +26,307 @@ pub enum Outcome {
     /// The program halted successfully.
     Halt,
     /// The program caused a problem and broke the machine.
-    Fault(String),
+    Fault {
+        /// What went wrong.
+        fault: Fault,
+        /// The IP of the instruction that caused the fault.
+        ip: usize,
+        /// The instruction that caused the fault.
+        instruction: Instruction,
+    },
     /// The program can continue running.
     Continue,
+    /// The program is waiting to read from an input channel which has run dry under
+    /// `EndOfInput::Suspend`. Supply a word with `provide_input` and resume; the input
+    /// instruction will run again.
+    NeedsInput {
+        /// The channel the program tried to read from.
+        channel: usize,
+    },
+    /// The program wrote a word, and the machine is streaming output, so it paused to let the
+    /// host see it. The program can continue running; if moving past the output halts or
+    /// faults, that is the outcome of the next step.
+    Output {
+        /// The channel the word was written to.
+        channel: usize,
+        /// The word written.
+        word: Word,
+    },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Halt => write!(f, "Halted."),
+            Outcome::Fault {
+                fault,
+                ip,
+                instruction,
+            } => write!(f, "Fault at IP {} ({}): {}", ip, instruction, fault),
+            Outcome::Continue => write!(f, "Running."),
+            Outcome::NeedsInput { channel } => {
+                write!(f, "Waiting for input on channel {}.", channel)
+            }
+            Outcome::Output { channel, word } => {
+                write!(f, "Wrote {} to channel {}.", word, channel)
+            }
+        }
+    }
+}
+
+/// The kinds of hardware error a program can cause.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Fault {
+    /// IP was advanced past the end of the program.
+    IpOverrun { ip: usize, len: usize },
+    /// A jump targeted a location outside the program.
+    JumpOutOfRange { target: JumpLocation, len: usize },
+    /// An instruction tried to store its result in a Literal.
+    WriteToLiteral { value: Word, literal: Word },
+    /// An instruction tried to write past the end of the machine's memory.
+    MemoryOutOfBounds { addr: Word },
+    /// A push moved the stack pointer to the bottom of memory.
+    StackOverflow,
+    /// An I/O instruction named a channel with no port attached.
+    NoSuchChannel { channel: Word },
+    /// An Input instruction found no more input to read.
+    InputExhausted,
+    /// An Output instruction couldn't write to the output.
+    OutputError(io::ErrorKind),
+    /// An `Illegal` instruction was executed.
+    IllegalInstruction,
+    /// A Div, Mod or IDiv instruction had a divisor of zero.
+    DivideByZero,
+    /// A relative jump by `offset` would land outside a program of length `len`.
+    RelativeJumpOutOfRange { offset: i64, len: usize },
+    /// A Ret instruction was executed with no Call in progress.
+    ReturnOnEmpty,
+    /// A Call instruction would have nested calls deeper than the machine allows.
+    CallDepthExceeded { depth: usize },
+    /// A floating-point instruction produced NaN or an infinity under `FloatPolicy::Fault`.
+    NonFiniteFloat,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Fault::*;
+        match *self {
+            IpOverrun { ip, len } => {
+                write!(f, "IP beyond program length. IP = {}, length = {}", ip, len)
+            }
+            JumpOutOfRange { target, len } => write!(
+                f,
+                "Attempt to jump to {} would overrun program of length {}.",
+                target, len
+            ),
+            WriteToLiteral { value, literal } => {
+                write!(f, "Tried to write {} to literal {}.", value, literal)
+            }
+            MemoryOutOfBounds { addr } => {
+                write!(f, "Tried to write out of available memory: {}", addr)
+            }
+            StackOverflow => write!(f, "Stack has overrun available memory!"),
+            NoSuchChannel { channel } => write!(f, "No I/O port attached to channel {}.", channel),
+            InputExhausted => write!(f, "Failed to read on input instruction: input exhausted."),
+            OutputError(kind) => write!(f, "Failed to write on output instruction: {}.", kind),
+            IllegalInstruction => write!(f, "Illegal instruction encountered."),
+            DivideByZero => write!(f, "Attempt to divide by zero."),
+            RelativeJumpOutOfRange { offset, len } => write!(
+                f,
+                "Attempt to jump by {} would leave program of length {}.",
+                offset, len
+            ),
+            ReturnOnEmpty => write!(f, "Attempt to return with no call in progress."),
+            CallDepthExceeded { depth } => {
+                write!(f, "Call would exceed the maximum call depth of {}.", depth)
+            }
+            NonFiniteFloat => write!(f, "Floating-point result was NaN or infinite."),
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// What the machine does when a particular kind of fault occurs.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub enum FaultAction {
+    /// Stop with an `Outcome::Fault`. This is the default.
+    #[default]
+    Fault,
+    /// Carry on as if the offending operation hadn't happened: a bad jump isn't taken,
+    /// a write to a literal is dropped, and so on. Running off the end of the program
+    /// can't be skipped, so there this acts like `Wrap`.
+    Skip,
+    /// Take IP modulo the length of the program, so running off the end or jumping out of
+    /// range lands back inside it. Faults with nothing to wrap act like `Skip`.
+    Wrap,
+    /// Stop with an `Outcome::Halt`, as if the program had finished normally.
+    Halt,
+}
+
+/// Chooses a `FaultAction` for each class of fault that programs can survive.
+///
+/// The default faults on everything, just like a machine without a policy. Faults not
+/// covered here, like running out of memory, always fault.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub struct FaultPolicy {
+    /// IP moved past the end of the program.
+    pub ip_overrun: FaultAction,
+    /// A jump or call targeted an instruction outside the program. This covers relative
+    /// jumps only under `RelativeJumpPolicy::Fault`; wrapping wraps their signed target.
+    pub jump_out_of_range: FaultAction,
+    /// An instruction tried to write to a `Literal`.
+    pub write_to_literal: FaultAction,
+    /// An `Illegal` instruction was executed.
+    pub illegal_instruction: FaultAction,
+    /// A division had a divisor of zero.
+    pub divide_by_zero: FaultAction,
+}
+
+impl FaultPolicy {
+    /// "Protected" semantics, as usually wanted for genetic programming: IP wraps around
+    /// the program, and every other recoverable fault is skipped.
+    pub fn protected() -> Self {
+        FaultPolicy {
+            ip_overrun: FaultAction::Wrap,
+            jump_out_of_range: FaultAction::Wrap,
+            write_to_literal: FaultAction::Skip,
+            illegal_instruction: FaultAction::Skip,
+            divide_by_zero: FaultAction::Skip,
+        }
+    }
+}
+
+/// Where Call and Ret keep return addresses.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub enum ReturnStack {
+    /// Push return addresses onto the hardware stack, along with the program's data.
+    /// The program can inspect and tamper with them. This is the default.
+    #[default]
+    Shared,
+    /// Keep return addresses on a separate stack which the program can't otherwise touch.
+    Separate,
+}
+
+/// The bit of the Flags register set when an arithmetic result is zero.
+pub const FLAG_ZERO: Word = 1;
+/// The bit of the Flags register set when an arithmetic result carried out of (or, for
+/// subtraction, borrowed into) the top bit, so that the unsigned result is wrong.
+pub const FLAG_CARRY: Word = 1 << 1;
+/// The bit of the Flags register set when the signed result of arithmetic overflowed.
+pub const FLAG_OVERFLOW: Word = 1 << 2;
+/// The bit of the Flags register set when an arithmetic result is negative, if signed.
+pub const FLAG_SIGN: Word = 1 << 3;
+
+/// What an input instruction does when its channel has no more input.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub enum EndOfInput {
+    /// Fault with `Fault::InputExhausted`. This is the default.
+    #[default]
+    Fault,
+    /// Read zero.
+    Zero,
+    /// Read the given value.
+    Sentinel(Word),
+    /// Read zero, and set the given register to 1. Every successful read on the channel sets
+    /// the register to 0, so the program can check it after each read.
+    Flag(Register),
+    /// Halt, keeping whatever output the program has produced so far.
+    Halt,
+    /// Stop with `Outcome::NeedsInput`, so the host can supply more input with
+    /// `Machine::provide_input` and resume.
+    Suspend,
+}
+
+/// What a relative jump does when its target lies outside the program.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub enum RelativeJumpPolicy {
+    /// Do whatever the `FaultPolicy` says to do with an absolute jump out of range, which by
+    /// default is to fault. This is the default.
+    #[default]
+    Fault,
+    /// Jump to the first or last instruction instead, whichever is nearer the target.
+    Clamp,
+    /// Take the target modulo the length of the program.
+    Wrap,
+}
+
+/// What floating-point instructions do when their result is NaN or an infinity.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub enum FloatPolicy {
+    /// Store the result as it is, just as IEEE-754 arithmetic would. This is the default.
+    #[default]
+    Propagate,
+    /// Fault with `Fault::NonFiniteFloat`, leaving the destination unchanged.
+    Fault,
+    /// Store 0.0 in place of NaN, and the largest finite float of the same sign in place of
+    /// an infinity.
+    Clamp,
+}
+
+/// A complete copy of everything a Machine knows, except for its I/O connections.
+///
+/// Taking a snapshot and restoring it later (or into a different Machine) lets a run be
+/// checkpointed, or forked to try out several continuations from a common prefix.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Debug, Clone)]
+pub struct MachineState {
+    /// The amount of memory the machine can use, at maximum.
+    pub max_words: usize,
+    /// The eight general purpouse registers.
+    pub registers: [Word; 8],
+    /// The stack pointer
+    pub sp: Word,
+    /// The base pointer
+    pub bp: Word,
+    /// The status flags
+    pub flags: Word,
+    /// The lowest address the stack may grow down to.
+    pub stack_limit: Word,
+    /// The instruction pointer, as an index into the program.
+    pub ip: usize,
+    /// The machine's memory
+    pub memory: Vec<Word>,
+    /// The loaded program
+    pub program: Program,
+    /// The number of instructions executed so far.
+    pub cycles: u64,
+    /// Return addresses, if they're kept on a separate stack.
+    pub call_stack: Vec<JumpLocation>,
+    /// The number of calls which have not yet returned.
+    pub call_depth: usize,
+    /// Words supplied with `provide_input` which haven't been read yet, for each input
+    /// channel in order.
+    pub provided: Vec<VecDeque<Word>>,
+    /// What moving past a streamed output led to, if that's still to be reported by the next
+    /// step. Outcomes can't be serialized, so this is left out of serialized snapshots; take
+    /// those once it has been reported.
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    pub pending: Option<Outcome>,
 }
 
 /// Represents the state of a machine, including its registers, its memory,
-/// its I/O Read and Write, and its program.
+/// its I/O ports, and its program.
+///
+/// The machine owns its input and output ports, `I` and `O`. Any `IoPort` will do,
+/// including a mutable reference to one, so a machine can either borrow its I/O or own it
+/// outright. A machine whose ports and observer are `Send` is itself `Send`, so one that
+/// owns its I/O can be sent between threads, stored or returned like any other value.
 ///
-/// The associated lifetime `'mach`
-/// represents the life of the machine; its I/O connections must live at
-/// least that long.
-pub struct Machine<'mach> {
+/// Ports are attached to numbered channels. The ports the machine is created with are
+/// channel 0, which Input and Output use; more can be attached with `attach_input` and
+/// `attach_output`, for InputFrom and OutputTo. To mix different kinds of port, use
+/// `Box<dyn IoPort>` for `I` or `O`.
+///
+/// `T` is an `ExecutionObserver` which is told about everything the machine does;
+/// by default it's `NoObserver`, which compiles away to nothing.
+pub struct Machine<I: IoPort, O: IoPort, T: ExecutionObserver = NoObserver> {
     /// The amount of memory the machine can use, at maximum.
     max_words: usize,
     /// The eight general purpouse registers, used for program operation.
@@ -35,6 +335,10 @@ pub struct Machine<'mach> {
     sp: Word,
     /// The base pointer
     bp: Word,
+    /// The status flags
+    flags: Word,
+    /// The stack may not grow down to this address
+    stack_limit: Word,
     /// The instruction pointer. Note that this is a pointer into the program vector, not
     /// the machine's data memory! It indexes a vector and does NOT advance by bytes or words.
     ip: usize,
@@ -42,34 +346,158 @@ pub struct Machine<'mach> {
     memory: Vec<Word>,
     /// Program code for the machine
     program: Program,
-    /// A reader to get input for the machine
-    input: &'mach mut Read,
-    /// A writer into which to put output from the machine
-    output: &'mach mut Write,
+    /// The number of instructions executed so far
+    cycles: u64,
+    /// Where return addresses are kept
+    return_stack: ReturnStack,
+    /// Return addresses, if they're kept on a separate stack
+    call_stack: Vec<JumpLocation>,
+    /// The number of calls which have not yet returned
+    call_depth: usize,
+    /// The deepest calls may nest before faulting
+    max_call_depth: usize,
+    /// What to do with relative jumps that land outside the program
+    relative_jumps: RelativeJumpPolicy,
+    /// What to do with floating-point results that aren't finite
+    float_policy: FloatPolicy,
+    /// What to do about recoverable faults
+    fault_policy: FaultPolicy,
+    /// Ports to get input for the machine, indexed by channel
+    inputs: Vec<I>,
+    /// What to do when each input channel runs dry
+    end_of_input: Vec<EndOfInput>,
+    /// Words supplied by the host for each input channel, read before the port itself
+    provided: Vec<VecDeque<Word>>,
+    /// Whether to pause after every output
+    stream_output: bool,
+    /// What moving past a streamed output led to, held back until the next step so the
+    /// output isn't lost
+    pending: Option<Outcome>,
+    /// Ports into which to put output from the machine, indexed by channel
+    outputs: Vec<O>,
+    /// Told about everything the machine does
+    observer: T,
 }
 
-impl<'mach> Machine<'mach> {
+impl<I: IoPort, O: IoPort> Machine<I, O> {
     /// Create a new Machine connected to the given I/O ports.
-    pub fn new(max_words: usize, input: &'mach mut Read, output: &'mach mut Write) -> Self {
-        Self {
-            max_words: max_words,
-            registers: [0; 8],
-            // Both SP and BP start at the top of memory; the stack grows downwards.
-            sp: (max_words - 1) as u64,
-            bp: (max_words - 1) as u64,
-            ip: 0,
-            memory: Vec::with_capacity(max_words),
-            program: vec![Instruction::Illegal],
-            input: input,
-            output: output,
+    /// Use a `MachineBuilder` to configure it further.
+    pub fn new(max_words: usize, input: I, output: O) -> Self {
+        MachineBuilder::new(max_words).build(input, output)
+    }
+}
+
+impl<I: IoPort, O: IoPort, T: ExecutionObserver> Machine<I, O, T> {
+    /// Install an observer on the machine, replacing any existing one.
+    pub fn with_observer<U: ExecutionObserver>(self, observer: U) -> Machine<I, O, U> {
+        Machine {
+            max_words: self.max_words,
+            registers: self.registers,
+            sp: self.sp,
+            bp: self.bp,
+            flags: self.flags,
+            stack_limit: self.stack_limit,
+            ip: self.ip,
+            memory: self.memory,
+            program: self.program,
+            cycles: self.cycles,
+            return_stack: self.return_stack,
+            call_stack: self.call_stack,
+            call_depth: self.call_depth,
+            max_call_depth: self.max_call_depth,
+            relative_jumps: self.relative_jumps,
+            float_policy: self.float_policy,
+            fault_policy: self.fault_policy,
+            inputs: self.inputs,
+            end_of_input: self.end_of_input,
+            provided: self.provided,
+            stream_output: self.stream_output,
+            pending: self.pending,
+            outputs: self.outputs,
+            observer,
         }
     }
 
+    /// Borrow the installed observer.
+    pub fn observer(&self) -> &T {
+        &self.observer
+    }
+
+    /// Mutably borrow the installed observer.
+    pub fn observer_mut(&mut self) -> &mut T {
+        &mut self.observer
+    }
+
+    /// Attach another input port, returning the number of the channel it's on.
+    /// It faults when it runs out of input, until told otherwise with `set_end_of_input`.
+    pub fn attach_input(&mut self, port: I) -> usize {
+        self.inputs.push(port);
+        self.end_of_input.push(EndOfInput::default());
+        self.provided.push(VecDeque::new());
+        self.inputs.len() - 1
+    }
+
+    /// Choose what happens when the given input channel runs out of input.
+    /// Does nothing if there's no port on that channel.
+    pub fn set_end_of_input(&mut self, channel: usize, policy: EndOfInput) {
+        if let Some(p) = self.end_of_input.get_mut(channel) {
+            *p = policy;
+        }
+    }
+
+    /// Attach another output port, returning the number of the channel it's on.
+    pub fn attach_output(&mut self, port: O) -> usize {
+        self.outputs.push(port);
+        self.outputs.len() - 1
+    }
+
+    /// Supply a word of input on the given channel, to be read before anything else the port
+    /// holds. This is how a host answers `Outcome::NeedsInput`.
+    /// Does nothing if there's no port on that channel.
+    pub fn provide_input(&mut self, channel: usize, word: Word) {
+        if let Some(p) = self.provided.get_mut(channel) {
+            p.push_back(word);
+        }
+    }
+
+    /// Choose whether every Output and OutputTo pauses the machine with `Outcome::Output`,
+    /// after writing the word to its port as usual. This is off by default.
+    pub fn set_stream_output(&mut self, stream: bool) {
+        self.stream_output = stream;
+    }
+
+    /// Mutably borrow the input port on the given channel, for instance to add more to it.
+    pub fn input_mut(&mut self, channel: usize) -> Option<&mut I> {
+        self.inputs.get_mut(channel)
+    }
+
+    /// Mutably borrow the output port on the given channel, for instance to collect what's
+    /// been written.
+    pub fn output_mut(&mut self, channel: usize) -> Option<&mut O> {
+        self.outputs.get_mut(channel)
+    }
+
+    /// Shut down the machine, giving back its input and output ports in channel order.
+    pub fn into_io(self) -> (Vec<I>, Vec<O>) {
+        (self.inputs, self.outputs)
+    }
+
     /// Load a program into the machine
     /// This resets the instruction pointer.
     pub fn load_program(&mut self, new: Vec<Instruction>) {
         self.program = new;
         self.ip = 0;
+        self.pending = None;
+    }
+
+    /// Borrow out the loaded program.
+    pub fn get_program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    /// The current instruction pointer; the index of the next instruction to execute.
+    pub fn get_ip(&self) -> usize {
+        self.ip
     }
 
     /// Borrow out the machine's internal memory for examination.
@@ -83,18 +511,119 @@ impl<'mach> Machine<'mach> {
         self.memory = new;
     }
 
+    /// Choose where Call and Ret keep return addresses. The default is `ReturnStack::Shared`.
+    pub fn set_return_stack(&mut self, return_stack: ReturnStack) {
+        self.return_stack = return_stack;
+    }
+
+    /// Where Call and Ret keep return addresses.
+    pub fn return_stack(&self) -> ReturnStack {
+        self.return_stack
+    }
+
+    /// Limit how deeply calls may nest; a Call beyond this depth is a Fault.
+    /// By default there is no limit, beyond the size of the stack.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Choose what relative jumps do when their target lies outside the program.
+    /// The default is `RelativeJumpPolicy::Fault`.
+    pub fn set_relative_jump_policy(&mut self, policy: RelativeJumpPolicy) {
+        self.relative_jumps = policy;
+    }
+
+    /// Choose what floating-point instructions do when their result is NaN or infinite.
+    /// The default is `FloatPolicy::Propagate`.
+    pub fn set_float_policy(&mut self, policy: FloatPolicy) {
+        self.float_policy = policy;
+    }
+
+    /// Choose how the machine reacts to each class of recoverable fault.
+    /// The default is to fault on all of them.
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    /// The number of instructions this machine has executed.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Copy out the complete state of the machine, apart from its I/O.
+    pub fn snapshot(&self) -> MachineState {
+        MachineState {
+            max_words: self.max_words,
+            registers: self.registers,
+            sp: self.sp,
+            bp: self.bp,
+            flags: self.flags,
+            stack_limit: self.stack_limit,
+            ip: self.ip,
+            memory: self.memory.clone(),
+            program: self.program.clone(),
+            cycles: self.cycles,
+            call_stack: self.call_stack.clone(),
+            call_depth: self.call_depth,
+            provided: self.provided.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+
+    /// Put the machine back into a previously snapshotted state.
+    /// The machine stays connected to its current I/O. Provided input for channels the
+    /// machine doesn't have is dropped.
+    pub fn restore(&mut self, state: MachineState) {
+        self.max_words = state.max_words;
+        self.registers = state.registers;
+        self.sp = state.sp;
+        self.bp = state.bp;
+        self.flags = state.flags;
+        self.stack_limit = state.stack_limit;
+        self.ip = state.ip;
+        self.memory = state.memory;
+        self.program = state.program;
+        self.cycles = state.cycles;
+        self.call_stack = state.call_stack;
+        self.call_depth = state.call_depth;
+        self.provided = state.provided;
+        self.provided.resize_with(self.inputs.len(), VecDeque::new);
+        self.pending = state.pending;
+    }
+
+    /// Build a Fault outcome, attributing it to the instruction at the current IP.
+    fn fault(&self, fault: Fault) -> Outcome {
+        Outcome::Fault {
+            fault,
+            ip: self.ip,
+            instruction: self
+                .program
+                .get(self.ip)
+                .cloned()
+                .unwrap_or(Instruction::Illegal),
+        }
+    }
+
     /// Advance to the next instruction (i.e., increment IP). This can cause a Fault, if IP ends up off the end.
     pub fn next_instr(&mut self) -> Outcome {
-        self.ip += 1;
-        if self.ip >= self.program.len() {
-            Outcome::Fault(format!(
-                "IP beyond program length. IP = {}, length = {}",
-                self.ip,
-                self.program.len()
-            ))
-        } else {
-            Outcome::Continue
+        let next = self.ip + 1;
+        if next < self.program.len() {
+            self.ip = next;
+            return Outcome::Continue;
         }
+        let outcome = match self.fault_policy.ip_overrun {
+            FaultAction::Fault => self.fault(Fault::IpOverrun {
+                ip: next,
+                len: self.program.len(),
+            }),
+            FaultAction::Halt => Outcome::Halt,
+            FaultAction::Skip | FaultAction::Wrap => {
+                self.ip = next % self.program.len().max(1);
+                return Outcome::Continue;
+            }
+        };
+        self.ip = next;
+        outcome
     }
 
     /// Write the given word value to the given address.
@@ -103,7 +632,15 @@ impl<'mach> Machine<'mach> {
     pub fn write_addr(&mut self, a: Address, v: Word) -> Outcome {
         use self::Address::*;
         match a {
-            Literal(l) => Outcome::Fault(format!("Tried to write {} to literal {}.", v, l)),
+            Literal(l) => match self.fault_policy.write_to_literal {
+                FaultAction::Fault => self.fault(Fault::WriteToLiteral {
+                    value: v,
+                    literal: l,
+                }),
+                FaultAction::Halt => Outcome::Halt,
+                // Dropping the write lets the rest of the instruction carry on.
+                FaultAction::Skip | FaultAction::Wrap => Outcome::Continue,
+            },
             RegAbs(r) => {
                 self.write_register(r, v);
                 Outcome::Continue
@@ -113,6 +650,18 @@ impl<'mach> Machine<'mach> {
                 let location = self.read_register(r);
                 self.write_memory(location, v)
             }
+            MemRegOffset(r, offset) => {
+                let location = self.read_register(r).wrapping_add(offset as Word);
+                self.write_memory(location, v)
+            }
+            MemRegIndexed(r, i) => {
+                let location = self.read_register(r).wrapping_add(self.read_register(i));
+                self.write_memory(location, v)
+            }
+            MemMem(l) => {
+                let location = self.read_memory(l);
+                self.write_memory(location, v)
+            }
         }
     }
 
@@ -124,6 +673,13 @@ impl<'mach> Machine<'mach> {
             RegAbs(r) => self.read_register(r),
             MemAbs(l) => self.read_memory(l),
             MemReg(r) => self.read_memory(self.read_register(r)),
+            MemRegOffset(r, offset) => {
+                self.read_memory(self.read_register(r).wrapping_add(offset as Word))
+            }
+            MemRegIndexed(r, i) => {
+                self.read_memory(self.read_register(r).wrapping_add(self.read_register(i)))
+            }
+            MemMem(l) => self.read_memory(self.read_memory(l)),
         }
     }
 
@@ -140,11 +696,14 @@ impl<'mach> Machine<'mach> {
             Register::R7 => self.registers[7],
             Register::SP => self.sp,
             Register::BP => self.bp,
+            Register::Flags => self.flags,
         }
     }
 
     /// Write a value into a register
     fn write_register(&mut self, r: Register, v: Word) {
+        let old = self.read_register(r);
+        self.observer.on_register_write(r, old, v);
         match r {
             Register::R0 => {
                 self.registers[0] = v;
@@ -176,21 +735,25 @@ impl<'mach> Machine<'mach> {
             Register::BP => {
                 self.bp = v;
             }
+            Register::Flags => {
+                self.flags = v;
+            }
         }
     }
 
     /// Write the provided value (v) into the provided memory address.
     /// If this is off the end of the provided memory, fault.
     fn write_memory(&mut self, l: Word, v: Word) -> Outcome {
-        let l = l as usize;
         // Memory must be at least the right length
-        if l > self.max_words {
-            return Outcome::Fault(format!("Tried to write out of available memory: {}", l));
+        if l >= self.max_words as Word {
+            return self.fault(Fault::MemoryOutOfBounds { addr: l });
         }
+        let l = l as usize;
         // OK, within the provided memory. Resize if needed.
-        if l > self.memory.len() {
-            self.memory.resize(l + 1 as usize, 0);
+        if l >= self.memory.len() {
+            self.memory.resize(l + 1, 0);
         }
+        self.observer.on_memory_write(l as Word, self.memory[l], v);
         self.memory[l] = v;
         Outcome::Continue
     }
@@ -198,53 +761,223 @@ impl<'mach> Machine<'mach> {
     /// Read a Word from the provided memory address.
     /// If this address is outsize of the provided memory, this returns 0.
     fn read_memory(&self, l: Word) -> Word {
-        let l = l as usize;
         // If it falls outside memory, just give back the default
-        if l > self.max_words {
-            0
-        } else if l > self.memory.len() {
+        if l >= self.memory.len() as Word {
             0
         } else {
-            self.memory[l]
+            self.memory[l as usize]
         }
     }
 
     fn absolute_jump(&mut self, l: JumpLocation) -> Outcome {
         if l < self.program.len() {
             self.ip = l;
-            Outcome::Continue
-        } else {
-            Outcome::Fault(format!(
-                "Attempt to jump to {} would overrun program of length {}.",
-                l,
-                self.program.len()
-            ))
+            return Outcome::Continue;
+        }
+        match self.fault_policy.jump_out_of_range {
+            FaultAction::Fault => self.fault(Fault::JumpOutOfRange {
+                target: l,
+                len: self.program.len(),
+            }),
+            FaultAction::Skip => self.next_instr(),
+            FaultAction::Wrap => {
+                self.ip = l % self.program.len().max(1);
+                Outcome::Continue
+            }
+            FaultAction::Halt => Outcome::Halt,
         }
     }
 
+    /// Jump by a signed offset from the current instruction, following the relative jump policy
+    /// if the target lies outside the program.
+    fn relative_jump(&mut self, offset: Word) -> Outcome {
+        let offset = offset as i64;
+        let len = self.program.len() as i128;
+        // Widen so that no offset can overflow.
+        let target = self.ip as i128 + offset as i128;
+        let target = if (0..len).contains(&target) {
+            target
+        } else {
+            match self.relative_jumps {
+                RelativeJumpPolicy::Fault => match self.fault_policy.jump_out_of_range {
+                    FaultAction::Fault => {
+                        return self.fault(Fault::RelativeJumpOutOfRange {
+                            offset,
+                            len: self.program.len(),
+                        })
+                    }
+                    FaultAction::Skip => return self.next_instr(),
+                    FaultAction::Wrap => target.rem_euclid(len.max(1)),
+                    FaultAction::Halt => return Outcome::Halt,
+                },
+                RelativeJumpPolicy::Clamp => target.clamp(0, (len - 1).max(0)),
+                RelativeJumpPolicy::Wrap => target.rem_euclid(len.max(1)),
+            }
+        };
+        self.absolute_jump(target as JumpLocation)
+    }
+
     pub fn execute_next(&mut self) -> Outcome {
+        if let Some(outcome) = self.pending.take() {
+            self.observer.on_outcome(&outcome);
+            return outcome;
+        }
+        // next_instr faults if IP goes over the end of the vector, but the program itself
+        // may be empty, so don't index blindly.
+        let outcome = match self.program.get(self.ip) {
+            Some(&instruction) => {
+                self.observer.on_instruction(self.ip, &instruction);
+                self.cycles += 1;
+                let outcome = self.dispatch(instruction);
+                // The instruction will be run again when there's input for it.
+                if let Outcome::NeedsInput { .. } = outcome {
+                    self.cycles -= 1;
+                }
+                outcome
+            }
+            None => self.fault(Fault::IpOverrun {
+                ip: self.ip,
+                len: self.program.len(),
+            }),
+        };
+        self.observer.on_outcome(&outcome);
+        outcome
+    }
+
+    /// Execute the given instruction, which must be the one at IP.
+    fn dispatch(&mut self, instruction: Instruction) -> Outcome {
         use Instruction::*;
-        // This index operation is safe because next_instr faults if IP goes over the
-        // end of the vector
-        match self.program[self.ip] {
+        match instruction {
             NoOp => self.ins_no_op(),
             Zero(a) => self.ins_zero(a),
             Move(a, b) => self.ins_move(a, b),
-            Output(a) => self.ins_output(a),
-            Input(a) => self.ins_input(a),
-            Add(a, b) => self.ins_generic_scalar(a, b, |va, vb| va.wrapping_add(vb)),
-            Sub(a, b) => self.ins_generic_scalar(a, b, |va, vb| va.wrapping_sub(vb)),
+            Output(a) => self.ins_output(0, a),
+            Input(a) => self.ins_input(0, a),
+            OutputTo(a, b) => {
+                let channel = self.read_addr(a);
+                self.ins_output(channel, b)
+            }
+            InputFrom(a, b) => {
+                let channel = self.read_addr(a);
+                self.ins_input(channel, b)
+            }
+            InputRemaining(a, b) => self.ins_input_remaining(a, b),
+            Add(a, b) => self.ins_generic_flagged(a, b, |va, vb, _| add_with_carry(va, vb, false)),
+            Sub(a, b) => self.ins_generic_flagged(a, b, |va, vb, _| sub_with_borrow(va, vb, false)),
+            AddWithCarry(a, b) => self.ins_generic_flagged(a, b, add_with_carry),
+            SubWithBorrow(a, b) => self.ins_generic_flagged(a, b, sub_with_borrow),
+            Mul(a, b) => self.ins_generic_flagged(a, b, |va, vb, _| {
+                let (r, carry) = va.overflowing_mul(vb);
+                (r, carry, carry)
+            }),
+            Div(a, b) => self.ins_generic_division(a, b, |va, vb| va / vb),
+            Mod(a, b) => self.ins_generic_division(a, b, |va, vb| va % vb),
+            IMul(a, b) => self.ins_generic_flagged(a, b, |va, vb, _| {
+                let (r, overflow) = (va as i64).overflowing_mul(vb as i64);
+                (r as Word, overflow, overflow)
+            }),
+            IDiv(a, b) => self
+                .ins_generic_division(a, b, |va, vb| (va as i64).wrapping_div(vb as i64) as Word),
+            And(a, b) => self.ins_generic_scalar(a, b, |va, vb| va & vb),
+            Or(a, b) => self.ins_generic_scalar(a, b, |va, vb| va | vb),
+            Xor(a, b) => self.ins_generic_scalar(a, b, |va, vb| va ^ vb),
+            Not(a) => self.ins_generic_unary(a, |v| !v),
+            // wrapping_shl and wrapping_shr only use the low six bits of the shift amount.
+            Shl(a, b) => self.ins_generic_scalar(a, b, |va, vb| va.wrapping_shl(vb as u32)),
+            Shr(a, b) => self.ins_generic_scalar(a, b, |va, vb| va.wrapping_shr(vb as u32)),
+            Sar(a, b) => {
+                self.ins_generic_scalar(a, b, |va, vb| (va as i64).wrapping_shr(vb as u32) as Word)
+            }
+            Rotl(a, b) => self.ins_generic_scalar(a, b, |va, vb| va.rotate_left((vb % 64) as u32)),
+            Rotr(a, b) => self.ins_generic_scalar(a, b, |va, vb| va.rotate_right((vb % 64) as u32)),
+            Neg(a) => self.ins_generic_flagged(a, a, |_, v, _| sub_with_borrow(0, v, false)),
+            Inc(a) => self.ins_generic_flagged(a, Address::Literal(1), |v, one, _| {
+                add_with_carry(v, one, false)
+            }),
+            Dec(a) => self.ins_generic_flagged(a, Address::Literal(1), |v, one, _| {
+                sub_with_borrow(v, one, false)
+            }),
             Jump(a) => self.ins_jump(a),
             JumpIfZero(a, b) => self.ins_generic_jump_single(a, b, |v| v == 0),
             JumpNotZero(a, b) => self.ins_generic_jump_single(a, b, |v| v != 0),
+            JumpFlags(a, b) => {
+                let flags = self.flags;
+                self.ins_generic_jump_single(a, b, |mask| flags & mask != 0)
+            }
+            JumpNoFlags(a, b) => {
+                let flags = self.flags;
+                self.ins_generic_jump_single(a, b, |mask| flags & mask == 0)
+            }
+            JumpRel(a) => self.ins_jump_rel(a),
+            JumpRelIfZero(a, b) => self.ins_generic_jump_rel_single(a, b, |v| v == 0),
+            JumpRelNotZero(a, b) => self.ins_generic_jump_rel_single(a, b, |v| v != 0),
+            JumpEq(a, b, c) => self.ins_generic_jump_double(a, b, c, |vb, vc| vb == vc),
+            JumpNe(a, b, c) => self.ins_generic_jump_double(a, b, c, |vb, vc| vb != vc),
+            JumpLt(a, b, c) => self.ins_generic_jump_double(a, b, c, |vb, vc| vb < vc),
+            JumpGt(a, b, c) => self.ins_generic_jump_double(a, b, c, |vb, vc| vb > vc),
+            JumpLe(a, b, c) => self.ins_generic_jump_double(a, b, c, |vb, vc| vb <= vc),
+            JumpGe(a, b, c) => self.ins_generic_jump_double(a, b, c, |vb, vc| vb >= vc),
+            JumpILt(a, b, c) => {
+                self.ins_generic_jump_double(a, b, c, |vb, vc| (vb as i64) < (vc as i64))
+            }
+            JumpIGt(a, b, c) => {
+                self.ins_generic_jump_double(a, b, c, |vb, vc| (vb as i64) > (vc as i64))
+            }
+            JumpILe(a, b, c) => {
+                self.ins_generic_jump_double(a, b, c, |vb, vc| (vb as i64) <= (vc as i64))
+            }
+            JumpIGe(a, b, c) => {
+                self.ins_generic_jump_double(a, b, c, |vb, vc| (vb as i64) >= (vc as i64))
+            }
+            SetEq(a, b) => self.ins_generic_scalar(a, b, |va, vb| (va == vb) as Word),
+            SetNe(a, b) => self.ins_generic_scalar(a, b, |va, vb| (va != vb) as Word),
+            SetLt(a, b) => self.ins_generic_scalar(a, b, |va, vb| (va < vb) as Word),
+            SetGt(a, b) => self.ins_generic_scalar(a, b, |va, vb| (va > vb) as Word),
+            SetLe(a, b) => self.ins_generic_scalar(a, b, |va, vb| (va <= vb) as Word),
+            SetGe(a, b) => self.ins_generic_scalar(a, b, |va, vb| (va >= vb) as Word),
+            SetILt(a, b) => {
+                self.ins_generic_scalar(a, b, |va, vb| ((va as i64) < (vb as i64)) as Word)
+            }
+            SetIGt(a, b) => {
+                self.ins_generic_scalar(a, b, |va, vb| ((va as i64) > (vb as i64)) as Word)
+            }
+            SetILe(a, b) => {
+                self.ins_generic_scalar(a, b, |va, vb| ((va as i64) <= (vb as i64)) as Word)
+            }
+            SetIGe(a, b) => {
+                self.ins_generic_scalar(a, b, |va, vb| ((va as i64) >= (vb as i64)) as Word)
+            }
+            FAdd(a, b) => self.ins_generic_float(a, b, |va, vb| va + vb),
+            FSub(a, b) => self.ins_generic_float(a, b, |va, vb| va - vb),
+            FMul(a, b) => self.ins_generic_float(a, b, |va, vb| va * vb),
+            FDiv(a, b) => self.ins_generic_float(a, b, |va, vb| va / vb),
+            FSqrt(a) => self.ins_generic_float(a, a, |va, _| va.sqrt()),
+            FSin(a) => self.ins_generic_float(a, a, |va, _| va.sin()),
+            FExp(a) => self.ins_generic_float(a, a, |va, _| va.exp()),
+            FLog(a) => self.ins_generic_float(a, a, |va, _| va.ln()),
+            IntToFloat(a) => self.ins_generic_unary(a, |va| (va as i64 as f64).to_bits()),
+            // Float to integer casts saturate, and take NaN to 0.
+            FloatToInt(a) => self.ins_generic_unary(a, |va| f64::from_bits(va) as i64 as Word),
+            FSetEq(a, b) => self.ins_generic_float_compare(a, b, |va, vb| va == vb),
+            FSetLt(a, b) => self.ins_generic_float_compare(a, b, |va, vb| va < vb),
+            FSetLe(a, b) => self.ins_generic_float_compare(a, b, |va, vb| va <= vb),
+            FSetGt(a, b) => self.ins_generic_float_compare(a, b, |va, vb| va > vb),
+            FSetGe(a, b) => self.ins_generic_float_compare(a, b, |va, vb| va >= vb),
+            Call(a) => self.ins_call(a),
+            Ret => self.ins_ret(),
             Push(a) => self.ins_push(a),
             Pop(a) => self.ins_pop(a),
             Halt => self.ins_halt(),
-            Illegal => Outcome::Fault("Illegal instruction encountered.".into()),
+            Illegal => match self.fault_policy.illegal_instruction {
+                FaultAction::Fault => self.fault(Fault::IllegalInstruction),
+                FaultAction::Skip | FaultAction::Wrap => self.next_instr(),
+                FaultAction::Halt => Outcome::Halt,
+            },
         }
     }
 
-    /// Execute instructions until a Halt or Fault occurs.
+    /// Execute instructions until a Halt or Fault occurs, or the machine suspends for I/O.
+    /// The machine can be run again after `Outcome::NeedsInput` or `Outcome::Output`.
     /// _BEWARE: This may run forever!_
     pub fn run(&mut self) -> Outcome {
         loop {
@@ -257,7 +990,8 @@ impl<'mach> Machine<'mach> {
         }
     }
 
-    /// Execute at most the given number of instructions, also stopping on a Halt or Fault condition.
+    /// Execute at most the given number of instructions, also stopping on a Halt or Fault condition,
+    /// or if the machine suspends for I/O.
     /// Returns the Outcome of the last instruction and the number of instructions executed.
     pub fn run_for(&mut self, cycles: u64) -> (Outcome, u64) {
         let mut instructions_remaining = cycles;
@@ -266,6 +1000,10 @@ impl<'mach> Machine<'mach> {
                 Outcome::Continue => {
                     instructions_remaining -= 1;
                 }
+                // Unlike the other ways of stopping, this one finished its instruction.
+                output @ Outcome::Output { .. } => {
+                    return (output, cycles - instructions_remaining + 1);
+                }
                 other => {
                     return (other, cycles - instructions_remaining);
                 }
@@ -301,23 +1039,87 @@ impl<'mach> Machine<'mach> {
         }
     }
 
-    /// Execute an Output instruction
-    fn ins_output(&mut self, a: Address) -> Outcome {
+    /// Execute an Output or OutputTo instruction
+    fn ins_output(&mut self, channel: Word, a: Address) -> Outcome {
         let v = self.read_addr(a);
-        match self.output.write_u64::<BigEndian>(v) {
-            Ok(_) => self.next_instr(),
-            Err(e) => Outcome::Fault(format!("Failed to write on output instruction: {}.", e)),
+        let port = match usize::try_from(channel)
+            .ok()
+            .and_then(|c| self.outputs.get_mut(c))
+        {
+            Some(port) => port,
+            None => return self.fault(Fault::NoSuchChannel { channel }),
+        };
+        match port.write_word(v) {
+            Ok(_) => {
+                self.observer.on_io(channel as usize, IoEvent::Output(v));
+                let advanced = self.next_instr();
+                if !self.stream_output {
+                    return advanced;
+                }
+                // Report the output first, and whatever moving on did next time.
+                if advanced != Outcome::Continue {
+                    self.pending = Some(advanced);
+                }
+                Outcome::Output {
+                    channel: channel as usize,
+                    word: v,
+                }
+            }
+            Err(e) => self.fault(Fault::OutputError(e.kind())),
         }
     }
 
-    /// Execute an Input instruction
-    fn ins_input(&mut self, a: Address) -> Outcome {
-        match self.input.read_u64::<BigEndian>() {
-            Ok(v) => match self.write_addr(a, v) {
-                Outcome::Continue => self.next_instr(),
-                o => o,
+    /// Execute an Input or InputFrom instruction
+    fn ins_input(&mut self, channel: Word, a: Address) -> Outcome {
+        let c = match usize::try_from(channel) {
+            Ok(c) if c < self.inputs.len() => c,
+            _ => return self.fault(Fault::NoSuchChannel { channel }),
+        };
+        let policy = self.end_of_input[c];
+        let read = match self.provided[c].pop_front() {
+            Some(v) => Some(v),
+            None => self.inputs[c].read_word(),
+        };
+        let v = match read {
+            Some(v) => {
+                self.observer.on_io(c, IoEvent::Input(v));
+                if let EndOfInput::Flag(r) = policy {
+                    self.write_register(r, 0);
+                }
+                v
+            }
+            None => match policy {
+                EndOfInput::Fault => return self.fault(Fault::InputExhausted),
+                EndOfInput::Zero => 0,
+                EndOfInput::Sentinel(v) => v,
+                EndOfInput::Flag(r) => {
+                    self.write_register(r, 1);
+                    0
+                }
+                EndOfInput::Halt => return Outcome::Halt,
+                EndOfInput::Suspend => return Outcome::NeedsInput { channel: c },
             },
-            Err(e) => Outcome::Fault(format!("Failed to read on input instruction: {}.", e)),
+        };
+        match self.write_addr(a, v) {
+            Outcome::Continue => self.next_instr(),
+            o => o,
+        }
+    }
+
+    /// Execute an InputRemaining instruction. Ports which can't tell give `Word::MAX`.
+    fn ins_input_remaining(&mut self, a: Address, b: Address) -> Outcome {
+        let channel = self.read_addr(a);
+        let c = match usize::try_from(channel) {
+            Ok(c) if c < self.inputs.len() => c,
+            _ => return self.fault(Fault::NoSuchChannel { channel }),
+        };
+        let remaining = match self.inputs[c].remaining() {
+            Some(r) => (r + self.provided[c].len()) as Word,
+            None => Word::MAX,
+        };
+        match self.write_addr(b, remaining) {
+            Outcome::Continue => self.next_instr(),
+            o => o,
         }
     }
 
@@ -336,6 +1138,109 @@ impl<'mach> Machine<'mach> {
         }
     }
 
+    /// Execute any 2-register scalar instruction which divides a by b, faulting if b is zero
+    fn ins_generic_division<F: FnOnce(Word, Word) -> Word>(
+        &mut self,
+        a: Address,
+        b: Address,
+        f: F,
+    ) -> Outcome {
+        let value_a = self.read_addr(a);
+        let value_b = self.read_addr(b);
+        if value_b == 0 {
+            return match self.fault_policy.divide_by_zero {
+                FaultAction::Fault => self.fault(Fault::DivideByZero),
+                // Leave a as it is.
+                FaultAction::Skip | FaultAction::Wrap => self.next_instr(),
+                FaultAction::Halt => Outcome::Halt,
+            };
+        }
+        match self.write_addr(a, f(value_a, value_b)) {
+            Outcome::Continue => self.next_instr(),
+            other => other,
+        }
+    }
+
+    /// Execute any arithmetic instruction which sets the flags. f gets the values of a and b and
+    /// the carry flag, and gives the result and whether it carried and overflowed.
+    fn ins_generic_flagged<F: FnOnce(Word, Word, bool) -> (Word, bool, bool)>(
+        &mut self,
+        a: Address,
+        b: Address,
+        f: F,
+    ) -> Outcome {
+        let value_a = self.read_addr(a);
+        let value_b = self.read_addr(b);
+        let (result, carry, overflow) = f(value_a, value_b, self.flags & FLAG_CARRY != 0);
+        match self.write_addr(a, result) {
+            Outcome::Continue => {
+                let mut flags = 0;
+                if result == 0 {
+                    flags |= FLAG_ZERO;
+                }
+                if carry {
+                    flags |= FLAG_CARRY;
+                }
+                if overflow {
+                    flags |= FLAG_OVERFLOW;
+                }
+                if (result as i64) < 0 {
+                    flags |= FLAG_SIGN;
+                }
+                self.write_register(Register::Flags, flags);
+                self.next_instr()
+            }
+            other => other,
+        }
+    }
+
+    /// Execute any floating-point arithmetic instruction, applying the float policy to results
+    /// which aren't finite. Unary instructions pass a as b, and ignore it.
+    fn ins_generic_float<F: FnOnce(f64, f64) -> f64>(
+        &mut self,
+        a: Address,
+        b: Address,
+        f: F,
+    ) -> Outcome {
+        let value_a = f64::from_bits(self.read_addr(a));
+        let value_b = f64::from_bits(self.read_addr(b));
+        let mut result = f(value_a, value_b);
+        if !result.is_finite() {
+            match self.float_policy {
+                FloatPolicy::Propagate => {}
+                FloatPolicy::Fault => return self.fault(Fault::NonFiniteFloat),
+                FloatPolicy::Clamp if result.is_nan() => result = 0.0,
+                FloatPolicy::Clamp => result = result.clamp(f64::MIN, f64::MAX),
+            }
+        }
+        match self.write_addr(a, result.to_bits()) {
+            Outcome::Continue => self.next_instr(),
+            other => other,
+        }
+    }
+
+    /// Execute any floating-point comparison, setting a to 1 if it holds or 0 if it doesn't.
+    /// Comparisons involving NaN never hold.
+    fn ins_generic_float_compare<F: FnOnce(f64, f64) -> bool>(
+        &mut self,
+        a: Address,
+        b: Address,
+        f: F,
+    ) -> Outcome {
+        self.ins_generic_scalar(a, b, |va, vb| {
+            f(f64::from_bits(va), f64::from_bits(vb)) as Word
+        })
+    }
+
+    /// Execute any 1-register scalar instruction
+    fn ins_generic_unary<F: FnOnce(Word) -> Word>(&mut self, a: Address, f: F) -> Outcome {
+        let value_a = self.read_addr(a);
+        match self.write_addr(a, f(value_a)) {
+            Outcome::Continue => self.next_instr(),
+            other => other,
+        }
+    }
+
     /// Execute an unconditional jump
     fn ins_jump(&mut self, a: Address) -> Outcome {
         let addr = self.read_addr(a) as JumpLocation;
@@ -358,73 +1263,192 @@ impl<'mach> Machine<'mach> {
         }
     }
 
+    /// Execute a relative jump instruction
+    fn ins_jump_rel(&mut self, a: Address) -> Outcome {
+        let offset = self.read_addr(a);
+        self.relative_jump(offset)
+    }
+
+    /// Execute any one-operand relative jump
+    fn ins_generic_jump_rel_single<F: FnOnce(Word) -> bool>(
+        &mut self,
+        a: Address,
+        b: Address,
+        f: F,
+    ) -> Outcome {
+        let offset = self.read_addr(a);
+        let value_b = self.read_addr(b);
+        if f(value_b) {
+            self.relative_jump(offset)
+        } else {
+            self.next_instr()
+        }
+    }
+
+    /// Execute any two-operand jump, which compares b and c to decide whether to jump to a
+    fn ins_generic_jump_double<F: FnOnce(Word, Word) -> bool>(
+        &mut self,
+        a: Address,
+        b: Address,
+        c: Address,
+        f: F,
+    ) -> Outcome {
+        let value_a = self.read_addr(a) as JumpLocation;
+        let value_b = self.read_addr(b);
+        let value_c = self.read_addr(c);
+        if f(value_b, value_c) {
+            self.absolute_jump(value_a)
+        } else {
+            self.next_instr()
+        }
+    }
+
     /// Execute a push instruction. Causes a fault if the stack has overrun the available
     /// memory.
     fn ins_push(&mut self, a: Address) -> Outcome {
         let val = self.read_addr(a);
-        // Scope for mutable borrow
-        self.sp -= 1;
-        if self.sp <= 0 {
-            Outcome::Fault("Stack has overrun available memory!".into())
-        } else {
-            // Copy out of immutable ref to self to satisfy borrow checker
-            let location = self.sp;
-            self.write_memory(location, val);
-            self.next_instr()
+        match self.push_word(val) {
+            Outcome::Continue => self.next_instr(),
+            other => other,
         }
     }
 
     /// Execute a pop instruction. If the stack is empty, this does not fault, but sets the target to
     /// zero.
     fn ins_pop(&mut self, a: Address) -> Outcome {
-        let val = if self.sp >= self.bp {
-            self.sp = self.bp;
-            0
-        } else {
-            self.read_memory(self.sp)
-        };
-        self.sp += 1;
-
+        let val = self.pop_word();
         match self.write_addr(a, val) {
             Outcome::Continue => self.next_instr(),
             other => other,
         }
     }
+
+    /// Push a word onto the stack, faulting if the stack has overrun the available memory.
+    fn push_word(&mut self, v: Word) -> Outcome {
+        // SP is an ordinary register, so the program may have put anything in it.
+        self.write_register(Register::SP, self.sp.wrapping_sub(1));
+        if self.sp <= self.stack_limit {
+            self.fault(Fault::StackOverflow)
+        } else {
+            // Copy out of immutable ref to self to satisfy borrow checker
+            let location = self.sp;
+            self.write_memory(location, v)
+        }
+    }
+
+    /// Pop a word from the stack. If the stack is empty, this gives zero and leaves SP at BP.
+    fn pop_word(&mut self) -> Word {
+        if self.sp >= self.bp {
+            self.write_register(Register::SP, self.bp);
+            0
+        } else {
+            let v = self.read_memory(self.sp);
+            self.write_register(Register::SP, self.sp + 1);
+            v
+        }
+    }
+
+    /// Execute a call instruction, saving the address of the next instruction and jumping to a.
+    fn ins_call(&mut self, a: Address) -> Outcome {
+        let mut target = self.read_addr(a) as JumpLocation;
+        // Check everything that can go wrong before touching the stack.
+        if target >= self.program.len() {
+            if self.fault_policy.jump_out_of_range != FaultAction::Wrap {
+                return self.absolute_jump(target);
+            }
+            target %= self.program.len().max(1);
+        }
+        if self.call_depth >= self.max_call_depth {
+            return self.fault(Fault::CallDepthExceeded {
+                depth: self.max_call_depth,
+            });
+        }
+        let return_address = self.ip + 1;
+        match self.return_stack {
+            ReturnStack::Shared => match self.push_word(return_address as Word) {
+                Outcome::Continue => {}
+                other => return other,
+            },
+            ReturnStack::Separate => self.call_stack.push(return_address),
+        }
+        self.call_depth += 1;
+        self.absolute_jump(target)
+    }
+
+    /// Execute a return instruction, jumping back to the instruction after the last call.
+    fn ins_ret(&mut self) -> Outcome {
+        if self.call_depth == 0 {
+            return self.fault(Fault::ReturnOnEmpty);
+        }
+        let return_address = match self.return_stack {
+            ReturnStack::Shared => self.pop_word() as JumpLocation,
+            // call_depth counts the entries on the separate stack, so it can't be empty.
+            ReturnStack::Separate => self.call_stack.pop().unwrap_or(0),
+        };
+        self.call_depth -= 1;
+        self.absolute_jump(return_address)
+    }
+}
+
+/// Add a, b and a carry bit, giving the result and whether it carried and overflowed.
+fn add_with_carry(a: Word, b: Word, carry: bool) -> (Word, bool, bool) {
+    let (partial, carry_a) = a.overflowing_add(b);
+    let (result, carry_b) = partial.overflowing_add(carry as Word);
+    let signed = a as i64 as i128 + b as i64 as i128 + carry as i128;
+    (result, carry_a || carry_b, signed != result as i64 as i128)
+}
+
+/// Subtract b and a borrow bit from a, giving the result and whether it borrowed and overflowed.
+fn sub_with_borrow(a: Word, b: Word, borrow: bool) -> (Word, bool, bool) {
+    let (partial, borrow_a) = a.overflowing_sub(b);
+    let (result, borrow_b) = partial.overflowing_sub(borrow as Word);
+    let signed = a as i64 as i128 - b as i64 as i128 - borrow as i128;
+    (
+        result,
+        borrow_a || borrow_b,
+        signed != result as i64 as i128,
+    )
 }
 
 /// Given a Program (that is, a Vec of Instructions), this function will manage creating a Machine and hooking up its
 /// Input and Output for you. It returns a tuple of the final outcome of the program, the number of instructions executed, and
 /// a Vector of the output.
 pub fn execute(program: Program, input: Vec<u64>, limit: Option<u64>) -> (Outcome, u64, Vec<u64>) {
-    use std::io::{Cursor, Seek};
-    // Create and fill a buffer of u8s with the values of the given u64s, in big endian
-    let mut internal_input = Cursor::new(Vec::with_capacity(input.len() * 8));
-    for v in input {
-        internal_input.write_u64::<BigEndian>(v).unwrap();
-    }
-    internal_input.seek(std::io::SeekFrom::Start(0)).unwrap();
-
-    // Create output buffer
-    let mut internal_output = Cursor::new(Vec::new());
-
-    // Actually run the machine.
-    let o;
-    let cycles;
-    {
-        let mut m = Machine::new(128, &mut internal_input, &mut internal_output);
+    execute_with(&MachineBuilder::new(128), program, input, limit)
+}
 
-        m.load_program(program);
-        let actual_limit = limit.unwrap_or(u64::max_value());
-        let (a, b) = m.run_for(actual_limit);
-        o = a;
-        cycles = b;
-    }
-    // Compose output into u64 values
-    let mut output = Vec::new();
-    internal_output.seek(std::io::SeekFrom::Start(0)).unwrap();
-    while let Ok(v) = internal_output.read_u64::<BigEndian>() {
-        output.push(v);
-    }
+/// Like `execute`, but builds the Machine from the given configuration rather than giving it
+/// 128 words of memory and the default settings.
+pub fn execute_with(
+    config: &MachineBuilder,
+    program: Program,
+    input: Vec<u64>,
+    limit: Option<u64>,
+) -> (Outcome, u64, Vec<u64>) {
+    // The machine reads straight from the input and writes into an empty output vector.
+    let mut m = config.build(VecDeque::from(input), Vec::new());
+    m.load_program(program);
+    let (o, cycles) = run_to_limit(&mut m, limit);
+    let (_, mut outputs) = m.into_io();
+    (o, cycles, outputs.remove(0))
+}
 
-    (o, cycles, output)
+/// Run a machine until it stops for good or has run for `limit` cycles, carrying on through
+/// streamed output, and give the outcome and the number of cycles run.
+fn run_to_limit<I: IoPort, O: IoPort, T: ExecutionObserver>(
+    m: &mut Machine<I, O, T>,
+    limit: Option<u64>,
+) -> (Outcome, u64) {
+    let actual_limit = limit.unwrap_or(u64::MAX);
+    let mut cycles = 0;
+    let o = loop {
+        let (o, n) = m.run_for(actual_limit - cycles);
+        cycles += n;
+        match o {
+            // The output is collected anyway, so there's no need to stop for it.
+            Outcome::Output { .. } => {}
+            other => break other,
+        }
+    };
+    (o, cycles)
 }
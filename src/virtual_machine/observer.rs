@@ -0,0 +1,77 @@
+//! Hooks for watching a Machine as it executes.
+use super::Outcome;
+use crate::*;
+
+/// A value moving through the machine's I/O.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum IoEvent {
+    /// A word was read from the input.
+    Input(Word),
+    /// A word was written to the output.
+    Output(Word),
+}
+
+/// Receives a callback for everything a Machine does.
+///
+/// Every method does nothing by default, so implementors only need to provide the
+/// ones they care about. Install an observer with `Machine::with_observer`.
+///
+/// # Example
+/// ```
+/// # use mlem::Instruction::*;
+/// # use mlem::Address::*;
+/// # use mlem::Register::*;
+/// # use mlem::virtual_machine::{Machine, ExecutionObserver};
+/// # use mlem::Instruction;
+/// # use std::collections::VecDeque;
+/// /// Counts how many times each instruction is executed.
+/// struct Coverage(Vec<u64>);
+///
+/// impl ExecutionObserver for Coverage {
+///     fn on_instruction(&mut self, ip: usize, _: &Instruction) {
+///         self.0[ip] += 1;
+///     }
+/// }
+///
+/// let mut machine = Machine::new(128, VecDeque::new(), Vec::new())
+///     .with_observer(Coverage(vec![0; 3]));
+/// machine.load_program(vec![Move(Literal(1), RegAbs(R0)), Jump(Literal(2)), Halt]);
+/// machine.run();
+/// assert_eq!(machine.observer().0, vec![1, 1, 1]);
+/// ```
+pub trait ExecutionObserver {
+    /// Called before the instruction at `ip` is executed.
+    fn on_instruction(&mut self, _ip: usize, _instruction: &Instruction) {}
+    /// Called whenever a register is written, including SP changing on push and pop.
+    fn on_register_write(&mut self, _register: Register, _old: Word, _new: Word) {}
+    /// Called whenever a word of memory is written.
+    fn on_memory_write(&mut self, _address: Word, _old: Word, _new: Word) {}
+    /// Called whenever a word is read from an input channel or written to an output channel.
+    fn on_io(&mut self, _channel: usize, _event: IoEvent) {}
+    /// Called with the outcome of every instruction, after it has executed.
+    fn on_outcome(&mut self, _outcome: &Outcome) {}
+}
+
+/// An observer which ignores everything. This is the default, and costs nothing.
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+pub struct NoObserver;
+
+impl ExecutionObserver for NoObserver {}
+
+impl<T: ExecutionObserver + ?Sized> ExecutionObserver for &mut T {
+    fn on_instruction(&mut self, ip: usize, instruction: &Instruction) {
+        (**self).on_instruction(ip, instruction)
+    }
+    fn on_register_write(&mut self, register: Register, old: Word, new: Word) {
+        (**self).on_register_write(register, old, new)
+    }
+    fn on_memory_write(&mut self, address: Word, old: Word, new: Word) {
+        (**self).on_memory_write(address, old, new)
+    }
+    fn on_io(&mut self, channel: usize, event: IoEvent) {
+        (**self).on_io(channel, event)
+    }
+    fn on_outcome(&mut self, outcome: &Outcome) {
+        (**self).on_outcome(outcome)
+    }
+}
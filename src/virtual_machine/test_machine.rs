@@ -6,7 +6,11 @@ use std::io::{Cursor, Seek};
 fn test_get_set_memory() {
     let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(128));
     let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(128));
-    let mut m = Machine::new(128, &mut input, &mut output);
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
 
     let memory = vec![0, 1, 2, 3, 4];
     let mem_copy = memory.clone();
@@ -28,7 +32,11 @@ fn test_get_set_memory() {
 fn test_reg_instructions() {
     let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
     let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-    let mut m = Machine::new(128, &mut input, &mut output);
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
 
     // The instruction: move 0xdeadbeef R0
     let instr = Instruction::Move(Address::Literal(0xDEADBEEF), Address::RegAbs(Register::R0));
@@ -47,7 +55,11 @@ fn test_io_instructions() {
     let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
     // The machine lives inside this block
     {
-        let mut m = Machine::new(128, &mut input, &mut output);
+        let mut m = Machine::new(
+            128,
+            ByteReader::new(&mut input),
+            ByteWriter::new(&mut output),
+        );
 
         // Create the assembly instruction:
         //  output 0xDEADBEEF
@@ -87,7 +99,11 @@ fn test_io_instructions() {
 fn test_run() {
     let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(128));
     let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(128));
-    let mut m = Machine::new(128, &mut input, &mut output);
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
 
     // A 4-instruction program.
     let program = vec![
@@ -295,3 +311,1368 @@ fn test_pointer_memory_access() {
         output
     );
 }
+
+#[test]
+fn test_faults() {
+    let cases = vec![
+        (
+            vec![Instruction::NoOp],
+            Fault::IpOverrun { ip: 1, len: 1 },
+            0,
+        ),
+        (
+            vec![Instruction::NoOp, Instruction::Jump(Address::Literal(9))],
+            Fault::JumpOutOfRange { target: 9, len: 2 },
+            1,
+        ),
+        (
+            vec![Instruction::Zero(Address::Literal(3)), Instruction::Halt],
+            Fault::WriteToLiteral {
+                value: 0,
+                literal: 3,
+            },
+            0,
+        ),
+        (
+            vec![Instruction::Zero(Address::MemAbs(128)), Instruction::Halt],
+            Fault::MemoryOutOfBounds { addr: 128 },
+            0,
+        ),
+        (
+            vec![
+                Instruction::Move(Address::Literal(1), Address::RegAbs(Register::SP)),
+                Instruction::Push(Address::Literal(1)),
+                Instruction::Halt,
+            ],
+            Fault::StackOverflow,
+            1,
+        ),
+        (
+            vec![
+                Instruction::Input(Address::RegAbs(Register::R0)),
+                Instruction::Halt,
+            ],
+            Fault::InputExhausted,
+            0,
+        ),
+        (vec![Instruction::Illegal], Fault::IllegalInstruction, 0),
+    ];
+
+    for (program, expected_fault, expected_ip) in cases {
+        let expected_instruction = program[expected_ip];
+        let (outcome, _, _) = execute(program, vec![], Some(10));
+        match outcome {
+            Outcome::Fault {
+                fault,
+                ip,
+                instruction,
+            } => {
+                assert!(
+                    fault == expected_fault,
+                    "Expected {:?}, got {:?}.",
+                    expected_fault,
+                    fault
+                );
+                assert!(ip == expected_ip, "Fault attributed to IP {}.", ip);
+                assert!(instruction == expected_instruction);
+            }
+            other => panic!("Expected {:?}, got {:?}.", expected_fault, other),
+        }
+    }
+}
+
+#[test]
+fn test_memory_bounds() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    // Memory has three words written; reading the word just past them gives 0, not a panic.
+    let config = MachineBuilder::new(16).memory(vec![1, 2, 3]);
+    let program = vec![
+        Output(MemAbs(2)),
+        Output(MemAbs(3)),
+        Output(MemAbs(15)),
+        Output(MemAbs(16)),
+        Output(MemAbs(u64::MAX)),
+        Halt,
+    ];
+    let (outcome, _, output) = execute_with(&config, program, vec![], None);
+    assert!(outcome == Outcome::Halt, "Got {:?}", outcome);
+    assert!(output == vec![3, 0, 0, 0, 0], "Got {:?}", output);
+
+    // The last word of memory can be written, but not the one after it.
+    let program = vec![Move(Literal(9), MemAbs(15)), Output(MemAbs(15)), Halt];
+    let (outcome, _, output) = execute_with(&config, program, vec![], None);
+    assert!(outcome == Outcome::Halt && output == vec![9]);
+    let program = vec![Move(Literal(9), MemAbs(16)), Halt];
+    let (outcome, _, _) = execute_with(&config, program, vec![], None);
+    assert!(
+        outcome
+            == Outcome::Fault {
+                fault: Fault::MemoryOutOfBounds { addr: 16 },
+                ip: 0,
+                instruction: Move(Literal(9), MemAbs(16)),
+            },
+        "Got {:?}",
+        outcome
+    );
+}
+
+#[test]
+fn test_fault_messages() {
+    let (outcome, _, _) = execute(vec![Instruction::NoOp], vec![], Some(10));
+    assert!(
+        outcome.to_string() == "Fault at IP 0 (noop): IP beyond program length. IP = 1, length = 1",
+        "Unexpected message: {}",
+        outcome
+    );
+    assert!(
+        Fault::JumpOutOfRange { target: 9, len: 2 }.to_string()
+            == "Attempt to jump to 9 would overrun program of length 2."
+    );
+    assert!(
+        Fault::OutputError(std::io::ErrorKind::WriteZero).to_string()
+            == "Failed to write on output instruction: write zero."
+    );
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut m = Machine::new(
+        128,
+        ByteReader::new(&mut input),
+        ByteWriter::new(&mut output),
+    );
+
+    // Count R0 up forever, keeping a copy in memory
+    m.load_program(vec![
+        Instruction::Add(Address::RegAbs(Register::R0), Address::Literal(1)),
+        Instruction::Move(Address::RegAbs(Register::R0), Address::MemAbs(5)),
+        Instruction::Push(Address::RegAbs(Register::R0)),
+        Instruction::Jump(Address::Literal(0)),
+    ]);
+    m.run_for(10);
+    let checkpoint = m.snapshot();
+    assert!(checkpoint.cycles == 10 && m.cycles() == 10);
+    assert!(
+        checkpoint.ip == 2,
+        "Snapshot taken at IP {}.",
+        checkpoint.ip
+    );
+
+    m.run_for(20);
+    let later = m.snapshot();
+    assert!(later != checkpoint, "Machine state did not change.");
+
+    // Going back and running the same number of cycles must end up in the same place.
+    m.restore(checkpoint.clone());
+    assert!(m.snapshot() == checkpoint);
+    m.run_for(20);
+    assert!(
+        m.snapshot() == later,
+        "Restored machine diverged: {:?} vs {:?}",
+        m.snapshot(),
+        later
+    );
+    assert!(m.read_addr(Address::MemAbs(5)) == 8);
+}
+
+/// Records every callback it receives, in order.
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+}
+
+impl ExecutionObserver for Recorder {
+    fn on_instruction(&mut self, ip: usize, instruction: &Instruction) {
+        self.events.push(format!("{}: {}", ip, instruction));
+    }
+    fn on_register_write(&mut self, register: Register, old: Word, new: Word) {
+        self.events.push(format!("{} {} -> {}", register, old, new));
+    }
+    fn on_memory_write(&mut self, address: Word, old: Word, new: Word) {
+        self.events
+            .push(format!("[{}] {} -> {}", address, old, new));
+    }
+    fn on_io(&mut self, _channel: usize, event: IoEvent) {
+        self.events.push(format!("{:?}", event));
+    }
+    fn on_outcome(&mut self, outcome: &Outcome) {
+        if *outcome != Outcome::Continue {
+            self.events.push(format!("{:?}", outcome));
+        }
+    }
+}
+
+#[test]
+fn test_observer() {
+    let mut input: Cursor<Vec<u8>> = Cursor::new(vec![0, 0, 0, 0, 0, 0, 0, 7]);
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let mut recorder = Recorder::default();
+    {
+        let mut m = Machine::new(8, ByteReader::new(&mut input), ByteWriter::new(&mut output))
+            .with_observer(&mut recorder);
+        m.load_program(vec![
+            Instruction::Input(Address::RegAbs(Register::R0)),
+            Instruction::Push(Address::RegAbs(Register::R0)),
+            Instruction::Move(Address::Literal(9), Address::MemAbs(2)),
+            Instruction::Output(Address::MemAbs(2)),
+            Instruction::Halt,
+        ]);
+        assert!(m.run() == Outcome::Halt);
+    }
+    let expected = vec![
+        "0: input R0",
+        "Input(7)",
+        "R0 0 -> 7",
+        "1: push R0",
+        "SP 7 -> 6",
+        "[6] 0 -> 7",
+        "2: move 9 [2]",
+        "[2] 0 -> 9",
+        "3: output [2]",
+        "Output(9)",
+        "4: halt",
+        "Halt",
+    ];
+    assert!(
+        recorder.events == expected,
+        "Observer saw {:#?}",
+        recorder.events
+    );
+}
+
+#[test]
+fn test_extended_arith() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let minus = |v: i64| v as u64;
+    // Each case: (instruction operating on R0, initial R0, expected R0). R1 holds 3 throughout.
+    let cases = vec![
+        (Mul(RegAbs(R0), RegAbs(R1)), 7, 21),
+        (Div(RegAbs(R0), RegAbs(R1)), 7, 2),
+        (Mod(RegAbs(R0), RegAbs(R1)), 7, 1),
+        (IMul(RegAbs(R0), RegAbs(R1)), minus(-7), minus(-21)),
+        (IDiv(RegAbs(R0), RegAbs(R1)), minus(-7), minus(-2)),
+        (
+            IDiv(RegAbs(R0), Literal(minus(-1))),
+            minus(i64::MIN),
+            minus(i64::MIN),
+        ),
+        (And(RegAbs(R0), RegAbs(R1)), 6, 2),
+        (Or(RegAbs(R0), RegAbs(R1)), 4, 7),
+        (Xor(RegAbs(R0), RegAbs(R1)), 6, 5),
+        (Not(RegAbs(R0)), 0, u64::MAX),
+        (Shl(RegAbs(R0), RegAbs(R1)), 1, 8),
+        (Shl(RegAbs(R0), Literal(65)), 1, 2),
+        (Shr(RegAbs(R0), RegAbs(R1)), minus(-8), (minus(-8)) >> 3),
+        (Sar(RegAbs(R0), RegAbs(R1)), minus(-8), minus(-1)),
+        (Rotl(RegAbs(R0), RegAbs(R1)), 1 << 62, 2),
+        (Rotr(RegAbs(R0), RegAbs(R1)), 2, 1 << 62),
+        (Neg(RegAbs(R0)), 5, minus(-5)),
+        (Inc(RegAbs(R0)), u64::MAX, 0),
+        (Dec(RegAbs(R0)), 0, u64::MAX),
+    ];
+    for (instruction, initial, expected) in cases {
+        let program = vec![
+            Move(Literal(initial), RegAbs(R0)),
+            Move(Literal(3), RegAbs(R1)),
+            instruction,
+            Output(RegAbs(R0)),
+            Halt,
+        ];
+        let (outcome, _, output) = execute(program, vec![], Some(10));
+        assert!(
+            outcome == Outcome::Halt,
+            "{} caused {:?}.",
+            instruction,
+            outcome
+        );
+        assert!(
+            output == vec![expected],
+            "{} on {:#x} produced {:#x?} rather than {:#x}.",
+            instruction,
+            initial,
+            output,
+            expected
+        );
+    }
+
+    for instruction in &[
+        Div(RegAbs(R0), Literal(0)),
+        Mod(RegAbs(R0), Literal(0)),
+        IDiv(RegAbs(R0), Literal(0)),
+    ] {
+        let (outcome, _, _) = execute(vec![*instruction, Halt], vec![], Some(10));
+        match outcome {
+            Outcome::Fault {
+                fault: Fault::DivideByZero,
+                ip: 0,
+                ..
+            } => {}
+            other => panic!("{} caused {:?} rather than a fault.", instruction, other),
+        }
+    }
+}
+
+#[test]
+fn test_compare() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let minus_one = u64::MAX;
+    // Each case: (instruction comparing R0 with R1, whether it should hold)
+    let cases = |r0, r1| {
+        let unsigned_lt = r0 < r1;
+        let signed_lt = (r0 as i64) < (r1 as i64);
+        vec![
+            (JumpEq as fn(_, _, _) -> _, SetEq as fn(_, _) -> _, r0 == r1),
+            (JumpNe, SetNe, r0 != r1),
+            (JumpLt, SetLt, unsigned_lt),
+            (JumpGt, SetGt, r0 > r1),
+            (JumpLe, SetLe, r0 <= r1),
+            (JumpGe, SetGe, !unsigned_lt),
+            (JumpILt, SetILt, signed_lt),
+            (JumpIGt, SetIGt, (r0 as i64) > (r1 as i64)),
+            (JumpILe, SetILe, (r0 as i64) <= (r1 as i64)),
+            (JumpIGe, SetIGe, !signed_lt),
+        ]
+    };
+    for &(r0, r1) in &[(1, 2), (2, 2), (minus_one, 1)] {
+        for (jump, set, holds) in cases(r0, r1) {
+            // Output 1 if the jump is taken, then output the result of the set instruction
+            let program = vec![
+                Move(Literal(r0), RegAbs(R0)),            // 0
+                Move(Literal(r1), RegAbs(R1)),            // 1
+                jump(Literal(5), RegAbs(R0), RegAbs(R1)), // 2
+                Output(Literal(0)),                       // 3
+                Jump(Literal(6)),                         // 4
+                Output(Literal(1)),                       // 5
+                set(RegAbs(R0), RegAbs(R1)),              // 6
+                Output(RegAbs(R0)),                       // 7
+                Halt,                                     // 8
+            ];
+            let expected = vec![holds as u64, holds as u64];
+            let (outcome, _, output) = execute(program.clone(), vec![], Some(20));
+            assert!(
+                outcome == Outcome::Halt,
+                "{:?} caused {:?}",
+                program,
+                outcome
+            );
+            assert!(
+                output == expected,
+                "{} / {} with R0 = {:#x} and R1 = {:#x} produced {:?} rather than {:?}.",
+                program[2],
+                program[6],
+                r0,
+                r1,
+                output,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn test_call_ret() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    // Calls a subroutine which doubles R0 twice, then recurses until R1 counts down to zero.
+    let program = vec![
+        Move(Literal(1), RegAbs(R0)),       // 0
+        Move(Literal(3), RegAbs(R1)),       // 1
+        Call(Literal(5)),                   // 2
+        Output(RegAbs(R0)),                 // 3
+        Halt,                               // 4
+        Add(RegAbs(R0), RegAbs(R0)),        // 5
+        Dec(RegAbs(R1)),                    // 6
+        JumpIfZero(Literal(9), RegAbs(R1)), // 7
+        Call(Literal(5)),                   // 8
+        Ret,                                // 9
+    ];
+    for &return_stack in &[ReturnStack::Shared, ReturnStack::Separate] {
+        let mut input = Cursor::new(Vec::new());
+        let mut output = Cursor::new(Vec::new());
+        let mut m = Machine::new(
+            128,
+            ByteReader::new(&mut input),
+            ByteWriter::new(&mut output),
+        );
+        m.set_return_stack(return_stack);
+        m.load_program(program.clone());
+        let (outcome, _) = m.run_for(100);
+        let state = m.snapshot();
+        assert!(
+            outcome == Outcome::Halt,
+            "{:?} return stack: {:?}",
+            return_stack,
+            outcome
+        );
+        assert!(state.call_depth == 0 && state.call_stack.is_empty());
+        assert!(state.sp == state.bp, "Stack not balanced after returning.");
+        drop(m);
+        output.set_position(0);
+        assert!(output.read_u64::<BigEndian>().unwrap() == 8);
+    }
+
+    // Return addresses on the shared stack are ordinary data.
+    let (_, _, output) = execute(
+        vec![Call(Literal(1)), Pop(RegAbs(R0)), Output(RegAbs(R0)), Halt],
+        vec![],
+        Some(10),
+    );
+    assert!(output == vec![1]);
+
+    let faults = vec![
+        (vec![Ret], Fault::ReturnOnEmpty, 0),
+        (
+            vec![Call(Literal(0))],
+            Fault::CallDepthExceeded { depth: 4 },
+            0,
+        ),
+        (
+            vec![Call(Literal(7)), Halt],
+            Fault::JumpOutOfRange { target: 7, len: 2 },
+            0,
+        ),
+    ];
+    for (program, fault, ip) in faults {
+        let mut input = Cursor::new(Vec::new());
+        let mut output = Cursor::new(Vec::new());
+        let mut m = Machine::new(
+            128,
+            ByteReader::new(&mut input),
+            ByteWriter::new(&mut output),
+        );
+        m.set_max_call_depth(4);
+        m.load_program(program.clone());
+        let (outcome, _) = m.run_for(100);
+        assert!(
+            outcome
+                == Outcome::Fault {
+                    fault,
+                    ip,
+                    instruction: program[ip]
+                },
+            "{:?} caused {:?}",
+            program,
+            outcome
+        );
+    }
+}
+
+#[test]
+fn test_relative_jumps() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let back = |n: i64| Literal(-n as u64);
+    // Counts R0 down from 3, outputting each value, using only relative jumps.
+    let program = vec![
+        Move(Literal(3), RegAbs(R0)),          // 0
+        Output(RegAbs(R0)),                    // 1
+        Dec(RegAbs(R0)),                       // 2
+        JumpRelIfZero(Literal(2), RegAbs(R0)), // 3
+        JumpRel(back(3)),                      // 4
+        Halt,                                  // 5
+    ];
+    let (outcome, _, output) = execute(program.clone(), vec![], Some(100));
+    assert!(outcome == Outcome::Halt, "{:?}", outcome);
+    assert!(output == vec![3, 2, 1], "Output {:?}", output);
+
+    // The same code still works when moved further into the program.
+    let mut shifted = vec![NoOp, NoOp];
+    shifted.extend(program);
+    let (_, _, output) = execute(shifted, vec![], Some(100));
+    assert!(output == vec![3, 2, 1], "Output {:?}", output);
+
+    // Jumping out of range under each policy: (offset, policy, outcome, R0)
+    let program = vec![
+        Move(Literal(1), RegAbs(R0)), // 0
+        JumpRel(RegAbs(R1)),          // 1
+        Halt,                         // 2
+        Move(Literal(3), RegAbs(R0)), // 3
+        Halt,                         // 4
+    ];
+    let fault = |offset| Outcome::Fault {
+        fault: Fault::RelativeJumpOutOfRange { offset, len: 5 },
+        ip: 1,
+        instruction: JumpRel(RegAbs(R1)),
+    };
+    let cases = vec![
+        (7, RelativeJumpPolicy::Fault, fault(7), 1),
+        (-2, RelativeJumpPolicy::Fault, fault(-2), 1),
+        (7, RelativeJumpPolicy::Clamp, Outcome::Halt, 1),
+        // Clamping to the start loops forever
+        (-2, RelativeJumpPolicy::Clamp, Outcome::Continue, 1),
+        (7, RelativeJumpPolicy::Wrap, Outcome::Halt, 3),
+        (i64::MIN, RelativeJumpPolicy::Wrap, Outcome::Halt, 3),
+    ];
+    for (offset, policy, expected, r0) in cases {
+        let mut input = Cursor::new(Vec::new());
+        let mut output = Cursor::new(Vec::new());
+        let mut m = Machine::new(
+            128,
+            ByteReader::new(&mut input),
+            ByteWriter::new(&mut output),
+        );
+        m.set_relative_jump_policy(policy);
+        m.load_program(program.clone());
+        m.write_addr(RegAbs(R1), offset as u64);
+        let (outcome, _) = m.run_for(10);
+        assert!(
+            outcome == expected,
+            "Offset {} under {:?} gave {:?}",
+            offset,
+            policy,
+            outcome
+        );
+        if outcome == Outcome::Halt {
+            assert!(m.read_addr(RegAbs(R0)) == r0);
+        }
+    }
+}
+
+#[test]
+fn test_fault_policy() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    // Each case: (program, policy, expected outcome, expected output)
+    let cases = vec![
+        // Running off the end
+        (
+            vec![Output(Literal(1))],
+            FaultPolicy {
+                ip_overrun: FaultAction::Halt,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![1],
+        ),
+        (
+            vec![Inc(RegAbs(R0)), JumpGe(Literal(4), RegAbs(R0), Literal(3))],
+            FaultPolicy {
+                ip_overrun: FaultAction::Wrap,
+                jump_out_of_range: FaultAction::Halt,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![],
+        ),
+        // Jumping out of range
+        (
+            vec![Jump(Literal(9)), Output(Literal(1)), Halt],
+            FaultPolicy {
+                jump_out_of_range: FaultAction::Skip,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![1],
+        ),
+        (
+            vec![Jump(Literal(8)), Output(Literal(1)), Halt],
+            FaultPolicy {
+                jump_out_of_range: FaultAction::Wrap,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![],
+        ),
+        (
+            vec![JumpRel(Literal(5)), Output(Literal(1)), Halt],
+            FaultPolicy::protected(),
+            Outcome::Halt,
+            vec![],
+        ),
+        (
+            vec![Output(Literal(2)), JumpRel(Literal(-5i64 as Word)), Halt],
+            FaultPolicy {
+                jump_out_of_range: FaultAction::Skip,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![2],
+        ),
+        // Writing to a literal drops the write, but the rest of the instruction happens
+        (
+            vec![Pop(Literal(0)), Output(Literal(1)), Halt],
+            FaultPolicy {
+                write_to_literal: FaultAction::Skip,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![1],
+        ),
+        (
+            vec![Zero(Literal(0)), Output(Literal(1)), Halt],
+            FaultPolicy {
+                write_to_literal: FaultAction::Halt,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![],
+        ),
+        // Illegal instructions and division by zero
+        (
+            vec![Illegal, Output(Literal(1)), Halt],
+            FaultPolicy {
+                illegal_instruction: FaultAction::Skip,
+                ..FaultPolicy::default()
+            },
+            Outcome::Halt,
+            vec![1],
+        ),
+        (
+            vec![
+                Move(Literal(5), RegAbs(R0)),
+                Div(RegAbs(R0), Literal(0)),
+                Output(RegAbs(R0)),
+                Halt,
+            ],
+            FaultPolicy::protected(),
+            Outcome::Halt,
+            vec![5],
+        ),
+        // Faults without a policy still fault
+        (
+            vec![Move(Literal(1), RegAbs(SP)), Push(Literal(1)), Halt],
+            FaultPolicy::protected(),
+            Outcome::Fault {
+                fault: Fault::StackOverflow,
+                ip: 1,
+                instruction: Push(Literal(1)),
+            },
+            vec![],
+        ),
+    ];
+    for (program, policy, expected, expected_output) in cases {
+        let mut input = Cursor::new(Vec::new());
+        let mut output = Cursor::new(Vec::new());
+        let mut m = Machine::new(
+            128,
+            ByteReader::new(&mut input),
+            ByteWriter::new(&mut output),
+        );
+        m.set_fault_policy(policy);
+        m.load_program(program.clone());
+        let (outcome, _) = m.run_for(20);
+        drop(m);
+        assert!(
+            outcome == expected,
+            "{:?} under {:?} gave {:?}",
+            program,
+            policy,
+            outcome
+        );
+        output.set_position(0);
+        let mut words = Vec::new();
+        while let Ok(w) = output.read_u64::<BigEndian>() {
+            words.push(w);
+        }
+        assert!(words == expected_output, "{:?} output {:?}", program, words);
+    }
+}
+
+#[test]
+fn test_builder() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let config = MachineBuilder::new(64)
+        .stack_base(40)
+        .stack_limit(37)
+        .register(R3, 7)
+        .memory(vec![0, 0, 0, 5]);
+    // Pushes until the stack overflows, having used addresses 39 and 38.
+    let program = vec![
+        Push(RegAbs(R3)), // 0
+        Push(MemAbs(3)),  // 1
+        Push(Literal(0)), // 2
+        Halt,             // 3
+    ];
+    let mut input = Cursor::new(Vec::new());
+    let mut output = Cursor::new(Vec::new());
+    let mut m = config.build(ByteReader::new(&mut input), ByteWriter::new(&mut output));
+    m.load_program(program);
+    let (outcome, _) = m.run_for(10);
+    assert!(
+        outcome
+            == Outcome::Fault {
+                fault: Fault::StackOverflow,
+                ip: 2,
+                instruction: Push(Literal(0)),
+            },
+        "Got {:?}",
+        outcome
+    );
+    assert!(m.read_addr(RegAbs(BP)) == 40);
+    assert!(m.get_memory()[38..40] == [5, 7]);
+
+    // The same configuration can be used again, and execute_with honours it.
+    let (outcome, _, output) = execute_with(
+        &config.clone().max_words(16).stack_base(8),
+        vec![
+            Output(RegAbs(SP)),
+            Output(MemAbs(3)),
+            Output(RegAbs(R3)),
+            Halt,
+        ],
+        vec![],
+        Some(10),
+    );
+    assert!(outcome == Outcome::Halt);
+    assert!(output == vec![8, 5, 7], "Output {:?}", output);
+}
+
+#[test]
+fn test_owned_io() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    fn assert_send_static<T: Send + 'static>(_: &T) {}
+
+    // A machine which owns its I/O can be built in one place and run in another.
+    let build = || {
+        let mut m = Machine::new(128, VecDeque::from(vec![20]), Vec::new());
+        m.load_program(vec![
+            Input(RegAbs(R0)),
+            Add(RegAbs(R0), RegAbs(R0)),
+            Output(RegAbs(R0)),
+            Halt,
+        ]);
+        m
+    };
+    let mut m = build();
+    assert_send_static(&m);
+    let handle = std::thread::spawn(move || {
+        let outcome = m.run();
+        (outcome, m)
+    });
+    let (outcome, m) = handle.join().unwrap();
+    assert!(outcome == Outcome::Halt);
+
+    let (inputs, outputs) = m.into_io();
+    assert!(inputs[0].is_empty());
+    assert!(outputs == [vec![40]]);
+}
+
+#[test]
+fn test_io_ports() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let program = vec![
+        Input(RegAbs(R0)),
+        Output(RegAbs(R0)),
+        Input(RegAbs(R0)),
+        Halt,
+    ];
+
+    // Byte streams in either byte order
+    for &endianness in &[Endianness::Big, Endianness::Little] {
+        let bytes = match endianness {
+            Endianness::Big => 0x0102u64.to_be_bytes(),
+            Endianness::Little => 0x0102u64.to_le_bytes(),
+        };
+        let input = ByteReader::with_endianness(Cursor::new(bytes.to_vec()), endianness);
+        let output = ByteWriter::with_endianness(Vec::new(), endianness);
+        let mut m = Machine::new(128, input, output);
+        m.load_program(program.clone());
+        let outcome = m.run();
+        // The second input finds the stream empty.
+        assert!(
+            outcome
+                == Outcome::Fault {
+                    fault: Fault::InputExhausted,
+                    ip: 2,
+                    instruction: Input(RegAbs(R0)),
+                },
+            "{:?} gave {:?}",
+            endianness,
+            outcome
+        );
+        let (_, mut outputs) = m.into_io();
+        assert!(outputs.remove(0).into_inner() == bytes.to_vec());
+    }
+
+    // A Vec can't be read from, and borrowed ports work just as well as owned ones.
+    let mut input = Vec::new();
+    let mut output = VecDeque::new();
+    let mut m = Machine::new(128, &mut input, &mut output);
+    m.load_program(vec![Output(Literal(5)), Input(RegAbs(R0)), Halt]);
+    let (outcome, _) = m.run_for(10);
+    assert!(outcome != Outcome::Halt);
+    drop(m);
+    assert!(output == [5]);
+}
+
+#[test]
+fn test_channels() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    // Reads a sensor reading from each of two extra channels, and writes their sum and
+    // difference to two extra output channels.
+    let program = vec![
+        InputFrom(Literal(1), RegAbs(R0)), // 0
+        InputFrom(Literal(2), RegAbs(R1)), // 1
+        Move(RegAbs(R0), RegAbs(R2)),      // 2
+        Add(RegAbs(R2), RegAbs(R1)),       // 3
+        Sub(RegAbs(R0), RegAbs(R1)),       // 4
+        OutputTo(Literal(1), RegAbs(R2)),  // 5
+        OutputTo(Literal(2), RegAbs(R0)),  // 6
+        Input(RegAbs(R3)),                 // 7
+        Output(RegAbs(R3)),                // 8
+        Move(Literal(9), RegAbs(R4)),      // 9
+        InputFrom(RegAbs(R4), RegAbs(R0)), // 10
+    ];
+    let mut m = Machine::new(128, VecDeque::from(vec![100]), VecDeque::new());
+    assert!(m.attach_input(VecDeque::from(vec![7])) == 1);
+    assert!(m.attach_input(VecDeque::from(vec![3])) == 2);
+    assert!(m.attach_output(VecDeque::new()) == 1);
+    assert!(m.attach_output(VecDeque::new()) == 2);
+    m.load_program(program);
+    let outcome = m.run();
+    assert!(
+        outcome
+            == Outcome::Fault {
+                fault: Fault::NoSuchChannel { channel: 9 },
+                ip: 10,
+                instruction: InputFrom(RegAbs(R4), RegAbs(R0)),
+            },
+        "Got {:?}",
+        outcome
+    );
+    m.input_mut(1).unwrap().push_back(1);
+    assert!(m.input_mut(3).is_none());
+
+    let (inputs, outputs) = m.into_io();
+    assert!(inputs.iter().map(|i| i.len()).eq(vec![0, 1, 0]));
+    assert!(
+        outputs == [vec![100], vec![10], vec![4]],
+        "Got {:?}",
+        outputs
+    );
+}
+
+#[test]
+fn test_end_of_input() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    // Reads and outputs three words from an input holding only one.
+    let program = vec![
+        InputRemaining(Literal(0), RegAbs(R1)), // 0
+        Output(RegAbs(R1)),                     // 1
+        Input(RegAbs(R0)),                      // 2
+        Output(RegAbs(R0)),                     // 3
+        Input(RegAbs(R0)),                      // 4
+        Output(RegAbs(R0)),                     // 5
+        Output(RegAbs(R7)),                     // 6
+        InputRemaining(Literal(0), RegAbs(R1)), // 7
+        Output(RegAbs(R1)),                     // 8
+        Halt,                                   // 9
+    ];
+    let cases = vec![
+        (
+            EndOfInput::Fault,
+            Outcome::Fault {
+                fault: Fault::InputExhausted,
+                ip: 4,
+                instruction: Input(RegAbs(R0)),
+            },
+            vec![1, 5],
+        ),
+        (EndOfInput::Zero, Outcome::Halt, vec![1, 5, 0, 0, 0]),
+        (
+            EndOfInput::Sentinel(99),
+            Outcome::Halt,
+            vec![1, 5, 99, 0, 0],
+        ),
+        (EndOfInput::Flag(R7), Outcome::Halt, vec![1, 5, 0, 1, 0]),
+        (EndOfInput::Halt, Outcome::Halt, vec![1, 5]),
+    ];
+    for (policy, expected, expected_output) in cases {
+        let config = MachineBuilder::new(128).end_of_input(policy);
+        let (outcome, _, output) = execute_with(&config, program.clone(), vec![5], Some(20));
+        assert!(outcome == expected, "{:?} gave {:?}", policy, outcome);
+        assert!(
+            output == expected_output,
+            "{:?} output {:?}",
+            policy,
+            output
+        );
+    }
+
+    // Policies are per channel, and ports which can't count their input say so.
+    let mut m = Machine::new(
+        128,
+        Box::new(VecDeque::new()) as Box<dyn IoPort>,
+        Vec::new(),
+    );
+    let bytes = m.attach_input(Box::new(ByteReader::new(std::io::empty())));
+    m.set_end_of_input(bytes, EndOfInput::Sentinel(7));
+    m.load_program(vec![
+        InputFrom(Literal(1), RegAbs(R0)),
+        Output(RegAbs(R0)),
+        InputRemaining(Literal(1), RegAbs(R0)),
+        Output(RegAbs(R0)),
+        Input(RegAbs(R0)),
+    ]);
+    let outcome = m.run();
+    assert!(
+        outcome
+            == Outcome::Fault {
+                fault: Fault::InputExhausted,
+                ip: 4,
+                instruction: Input(RegAbs(R0)),
+            },
+        "Got {:?}",
+        outcome
+    );
+    assert!(m.into_io().1 == [vec![7, u64::MAX]]);
+}
+
+#[test]
+fn test_suspend_and_stream() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    // Doubles each input until it reads a zero.
+    let program = vec![
+        Input(RegAbs(R0)),                  // 0
+        JumpIfZero(Literal(4), RegAbs(R0)), // 1
+        Add(RegAbs(R0), RegAbs(R0)),        // 2
+        JumpRel(Literal(2)),                // 3
+        Halt,                               // 4
+        Output(RegAbs(R0)),                 // 5
+        Jump(Literal(0)),                   // 6
+    ];
+    let config = MachineBuilder::new(128)
+        .end_of_input(EndOfInput::Suspend)
+        .stream_output(true);
+    let mut m = config.build(VecDeque::from(vec![1]), Vec::new());
+    m.load_program(program);
+
+    // The environment: feeds in 3, 4 and then 0, recording what comes out.
+    let mut feed = vec![3, 4, 0].into_iter();
+    let mut seen = Vec::new();
+    loop {
+        let (outcome, _) = m.run_for(100);
+        match outcome {
+            Outcome::NeedsInput { channel } => {
+                assert!(channel == 0);
+                m.provide_input(channel, feed.next().unwrap());
+            }
+            Outcome::Output { channel, word } => {
+                assert!(channel == 0);
+                seen.push(word);
+            }
+            other => {
+                assert!(other == Outcome::Halt, "Got {:?}", other);
+                break;
+            }
+        }
+    }
+    assert!(seen == vec![2, 6, 8], "Saw {:?}", seen);
+    // Waiting for input doesn't count as executing anything.
+    assert!(m.cycles() == 21, "Took {} cycles", m.cycles());
+    assert!(feed.next().is_none());
+    // Streamed words are still written to the port.
+    assert!(m.into_io().1 == [vec![2, 6, 8]]);
+}
+
+#[test]
+fn test_stream_output_at_end() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    // The output is the last instruction, so moving past it stops the machine.
+    for &(policy, ref then) in &[
+        (
+            FaultAction::Fault,
+            Outcome::Fault {
+                fault: Fault::IpOverrun { ip: 2, len: 2 },
+                ip: 1,
+                instruction: Output(Literal(7)),
+            },
+        ),
+        (FaultAction::Halt, Outcome::Halt),
+    ] {
+        let config = MachineBuilder::new(16)
+            .stream_output(true)
+            .fault_policy(FaultPolicy {
+                ip_overrun: policy,
+                ..FaultPolicy::default()
+            });
+        let mut m = config.build(VecDeque::new(), Vec::new());
+        m.load_program(vec![NoOp, Output(Literal(7))]);
+        // The output counts as executed, both by run_for and by the machine.
+        let (outcome, n) = m.run_for(10);
+        assert!(
+            outcome
+                == Outcome::Output {
+                    channel: 0,
+                    word: 7
+                },
+            "Got {:?}",
+            outcome
+        );
+        assert!(
+            n == 2 && m.cycles() == 2,
+            "Ran {} cycles, machine says {}",
+            n,
+            m.cycles()
+        );
+        // What moving on did is reported next, without running anything more.
+        assert!(m.run_for(10) == (then.clone(), 0));
+
+        let (outcome, cycles, output) =
+            execute_with(&config, vec![NoOp, Output(Literal(7))], vec![], None);
+        assert!(outcome == *then && cycles == 2 && output == vec![7]);
+    }
+}
+
+#[test]
+fn test_snapshot_keeps_provided_input() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let program = vec![
+        Input(RegAbs(R0)),
+        Input(RegAbs(R1)),
+        Output(RegAbs(R0)),
+        Output(RegAbs(R1)),
+        Halt,
+    ];
+    let config = MachineBuilder::new(16).end_of_input(EndOfInput::Suspend);
+    let mut m = config.build(VecDeque::new(), Vec::new());
+    m.load_program(program.clone());
+    assert!(m.run_for(10).0 == Outcome::NeedsInput { channel: 0 });
+    m.provide_input(0, 5);
+    m.provide_input(0, 6);
+    let state = m.snapshot();
+    assert!(state.provided == vec![VecDeque::from(vec![5, 6])]);
+
+    // A fresh machine picks up where the first left off, without being given the input again.
+    let mut restored = config.build(VecDeque::new(), Vec::new());
+    restored.restore(state);
+    assert!(restored.run_for(10).0 == Outcome::Halt);
+    assert!(restored.into_io().1 == [vec![5, 6]]);
+
+    // A snapshot taken just after a streamed output at the end of the program keeps what
+    // moving past it led to, as well as the stack limit.
+    let config = MachineBuilder::new(16).stream_output(true).stack_limit(3);
+    let mut m = config.build(VecDeque::new(), Vec::new());
+    m.load_program(vec![NoOp, Output(Literal(7))]);
+    assert!(
+        m.run_for(10)
+            == (
+                Outcome::Output {
+                    channel: 0,
+                    word: 7
+                },
+                2
+            )
+    );
+    let state = m.snapshot();
+    let overrun = Outcome::Fault {
+        fault: Fault::IpOverrun { ip: 2, len: 2 },
+        ip: 1,
+        instruction: Output(Literal(7)),
+    };
+    assert!(state.pending == Some(overrun.clone()) && state.stack_limit == 3);
+    let mut restored = MachineBuilder::new(16).build(VecDeque::new(), Vec::new());
+    restored.restore(state.clone());
+    assert!(restored.snapshot() == state);
+    assert!(restored.run_for(10) == (overrun, 0));
+}
+
+#[test]
+fn test_flags() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let min = i64::MIN as u64;
+    // Each case: (instruction operating on R0, initial R0, R1, expected R0, expected flags).
+    let cases = vec![
+        (Add(RegAbs(R0), RegAbs(R1)), 2, 3, 5, 0),
+        (
+            Add(RegAbs(R0), RegAbs(R1)),
+            u64::MAX,
+            1,
+            0,
+            FLAG_ZERO | FLAG_CARRY,
+        ),
+        (
+            Add(RegAbs(R0), RegAbs(R1)),
+            min - 1,
+            1,
+            min,
+            FLAG_OVERFLOW | FLAG_SIGN,
+        ),
+        (Sub(RegAbs(R0), RegAbs(R1)), 3, 3, 0, FLAG_ZERO),
+        (
+            Sub(RegAbs(R0), RegAbs(R1)),
+            2,
+            3,
+            u64::MAX,
+            FLAG_CARRY | FLAG_SIGN,
+        ),
+        (Sub(RegAbs(R0), RegAbs(R1)), min, 1, min - 1, FLAG_OVERFLOW),
+        (Inc(RegAbs(R0)), u64::MAX, 0, 0, FLAG_ZERO | FLAG_CARRY),
+        (Dec(RegAbs(R0)), 0, 0, u64::MAX, FLAG_CARRY | FLAG_SIGN),
+        (
+            Neg(RegAbs(R0)),
+            min,
+            0,
+            min,
+            FLAG_CARRY | FLAG_OVERFLOW | FLAG_SIGN,
+        ),
+        (
+            Mul(RegAbs(R0), RegAbs(R1)),
+            1 << 32,
+            1 << 32,
+            0,
+            FLAG_ZERO | FLAG_CARRY | FLAG_OVERFLOW,
+        ),
+        (
+            IMul(RegAbs(R0), RegAbs(R1)),
+            u64::MAX,
+            3,
+            -3i64 as u64,
+            FLAG_SIGN,
+        ),
+    ];
+    for (instruction, r0, r1, expected, expected_flags) in cases {
+        let program = vec![
+            Move(Literal(r0), RegAbs(R0)),
+            Move(Literal(r1), RegAbs(R1)),
+            instruction,
+            Output(RegAbs(R0)),
+            Output(RegAbs(Flags)),
+            Halt,
+        ];
+        let (outcome, _, output) = execute(program, vec![], Some(10));
+        assert!(
+            outcome == Outcome::Halt,
+            "{} caused {:?}",
+            instruction,
+            outcome
+        );
+        assert!(
+            output == vec![expected, expected_flags],
+            "{} with R0 = {:#x}, R1 = {:#x} gave {:?}",
+            instruction,
+            r0,
+            r1,
+            output
+        );
+    }
+
+    // 128-bit addition: (2^64 - 1) + (2^64 + 1) = 2^65, held as (low, high) in (R0, R1) and
+    // (R2, R3). Then jump on the carry out of the high word, which there isn't.
+    let program = vec![
+        Move(Literal(u64::MAX), RegAbs(R0)),          // 0
+        Move(Literal(1), RegAbs(R2)),                 // 1
+        Move(Literal(1), RegAbs(R3)),                 // 2
+        Add(RegAbs(R0), RegAbs(R2)),                  // 3
+        AddWithCarry(RegAbs(R1), RegAbs(R3)),         // 4
+        JumpFlags(Literal(9), Literal(FLAG_CARRY)),   // 5
+        Output(RegAbs(R0)),                           // 6
+        Output(RegAbs(R1)),                           // 7
+        JumpNoFlags(Literal(10), Literal(FLAG_ZERO)), // 8
+        Halt,                                         // 9
+        // Subtract the second number back off again
+        Sub(RegAbs(R0), RegAbs(R2)),           // 10
+        SubWithBorrow(RegAbs(R1), RegAbs(R3)), // 11
+        Output(RegAbs(R0)),                    // 12
+        Output(RegAbs(R1)),                    // 13
+        Halt,                                  // 14
+    ];
+    let (outcome, _, output) = execute(program, vec![], Some(20));
+    assert!(outcome == Outcome::Halt);
+    assert!(output == vec![0, 2, u64::MAX, 0], "Got {:?}", output);
+}
+
+#[test]
+fn test_floats() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let f = f64::to_bits;
+    // Each case: (instruction operating on R0, initial R0, R1, expected R0).
+    let cases = vec![
+        (FAdd(RegAbs(R0), RegAbs(R1)), f(1.5), f(2.25), f(3.75)),
+        (FSub(RegAbs(R0), RegAbs(R1)), f(1.5), f(2.25), f(-0.75)),
+        (FMul(RegAbs(R0), RegAbs(R1)), f(1.5), f(-2.0), f(-3.0)),
+        (FDiv(RegAbs(R0), RegAbs(R1)), f(1.0), f(4.0), f(0.25)),
+        (FSqrt(RegAbs(R0)), f(16.0), 0, f(4.0)),
+        (FSin(RegAbs(R0)), f(0.0), 0, f(0.0)),
+        (FExp(RegAbs(R0)), f(0.0), 0, f(1.0)),
+        (FLog(RegAbs(R0)), f(1.0), 0, f(0.0)),
+        (IntToFloat(RegAbs(R0)), -3i64 as u64, 0, f(-3.0)),
+        (FloatToInt(RegAbs(R0)), f(-3.75), 0, -3i64 as u64),
+        (FloatToInt(RegAbs(R0)), f(1e300), 0, i64::MAX as u64),
+        (FSetLt(RegAbs(R0), RegAbs(R1)), f(-1.0), f(0.5), 1),
+        (FSetGe(RegAbs(R0), RegAbs(R1)), f(-1.0), f(0.5), 0),
+        (FSetEq(RegAbs(R0), RegAbs(R1)), f(0.0), f(-0.0), 1),
+        (FSetEq(RegAbs(R0), RegAbs(R1)), f(f64::NAN), f(f64::NAN), 0),
+    ];
+    for (instruction, r0, r1, expected) in cases {
+        let program = vec![
+            Move(Literal(r0), RegAbs(R0)),
+            Move(Literal(r1), RegAbs(R1)),
+            instruction,
+            Output(RegAbs(R0)),
+            Halt,
+        ];
+        let (outcome, _, output) = execute(program, vec![], Some(10));
+        assert!(
+            outcome == Outcome::Halt,
+            "{} caused {:?}",
+            instruction,
+            outcome
+        );
+        assert!(
+            output == vec![expected],
+            "{} with R0 = {:#x}, R1 = {:#x} gave {:?}",
+            instruction,
+            r0,
+            r1,
+            output
+        );
+    }
+
+    // Dividing by zero and taking the log of a negative number, under each policy.
+    let program = vec![
+        Move(Literal(f(1.0)), RegAbs(R0)),
+        FDiv(RegAbs(R0), Literal(f(0.0))),
+        Output(RegAbs(R0)),
+        Move(Literal(f(-1.0)), RegAbs(R0)),
+        FLog(RegAbs(R0)),
+        Output(RegAbs(R0)),
+        Halt,
+    ];
+    let (outcome, _, output) = execute(program.clone(), vec![], Some(10));
+    assert!(outcome == Outcome::Halt);
+    assert!(output[0] == f(f64::INFINITY));
+    assert!(f64::from_bits(output[1]).is_nan());
+
+    let config = MachineBuilder::new(128).float_policy(FloatPolicy::Clamp);
+    let (outcome, _, output) = execute_with(&config, program.clone(), vec![], Some(10));
+    assert!(outcome == Outcome::Halt);
+    assert!(output == vec![f(f64::MAX), f(0.0)], "Got {:?}", output);
+
+    let config = MachineBuilder::new(128).float_policy(FloatPolicy::Fault);
+    let (outcome, _, output) = execute_with(&config, program, vec![], Some(10));
+    assert!(
+        outcome
+            == Outcome::Fault {
+                fault: Fault::NonFiniteFloat,
+                ip: 1,
+                instruction: FDiv(RegAbs(R0), Literal(f(0.0))),
+            },
+        "Got {:?}",
+        outcome
+    );
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_addressing_modes() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    let program = vec![
+        // A stack frame with two arguments, at BP and just above it
+        Push(Literal(30)),
+        Push(Literal(12)),
+        Move(RegAbs(SP), RegAbs(BP)),
+        Move(MemRegOffset(BP, 0), RegAbs(R0)),
+        Add(RegAbs(R0), MemRegOffset(BP, 1)),
+        Output(RegAbs(R0)),
+        // An array at 0x10, indexed by R2
+        Move(Literal(0x10), RegAbs(R1)),
+        Move(Literal(2), RegAbs(R2)),
+        Move(Literal(7), MemRegIndexed(R1, R2)),
+        Output(MemAbs(0x12)),
+        // A pointer at 0x20 to the same element
+        Move(Literal(0x12), MemAbs(0x20)),
+        Inc(MemMem(0x20)),
+        Output(MemMem(0x20)),
+        // Offsets wrap around the address space
+        Move(Literal(0x13), RegAbs(R3)),
+        Move(Literal(5), MemRegOffset(R3, -1)),
+        Output(MemAbs(0x12)),
+        Halt,
+    ];
+    let (outcome, _, output) = execute(program, vec![], Some(100));
+    assert!(outcome == Outcome::Halt, "Got {:?}", outcome);
+    assert!(output == vec![42, 7, 8, 5], "Got {:?}", output);
+}
+
+#[test]
+fn test_execute_batch() {
+    use crate::Address::*;
+    use crate::Instruction::*;
+    use crate::Register::*;
+    fn assert_send<T: Send>() {}
+    assert_send::<Machine<VecDeque<Word>, Vec<Word>>>();
+
+    // Every run changes memory, a register and the stack, which must not leak into the next.
+    let accumulate = vec![
+        Input(RegAbs(R0)),
+        Add(RegAbs(R0), MemAbs(0)),
+        Move(RegAbs(R0), MemAbs(0)),
+        Output(MemAbs(0)),
+        Output(RegAbs(R3)),
+        Move(Literal(0), RegAbs(R3)),
+        Push(RegAbs(R0)),
+        Halt,
+    ];
+    let spin = vec![Output(Literal(1)), Jump(Literal(0))];
+    let starve = vec![Input(RegAbs(R0)), Input(RegAbs(R1)), Halt];
+    let runs: Vec<(Program, Vec<Word>)> = (0..40)
+        .map(|i| match i % 3 {
+            0 => (accumulate.clone(), vec![i]),
+            1 => (spin.clone(), vec![]),
+            _ => (starve.clone(), vec![i]),
+        })
+        .collect();
+    let config = MachineBuilder::new(64)
+        .memory(vec![10])
+        .register(R3, 7)
+        .stream_output(true);
+    let serial: Vec<_> = runs
+        .iter()
+        .map(|(program, input)| execute_with(&config, program.clone(), input.clone(), Some(50)))
+        .collect();
+    assert!(serial[0] == (Outcome::Halt, 7, vec![10, 7]));
+    assert!(serial[1].1 == 50 && serial[1].2.len() == 25);
+    assert!(matches!(serial[2].0, Outcome::Fault { .. }));
+    assert!(serial[3].2 == vec![13, 7]);
+
+    let runs: Vec<(&[Instruction], &[Word])> = runs
+        .iter()
+        .map(|(program, input)| (&program[..], &input[..]))
+        .collect();
+    for &threads in &[1, 3, 8, 100] {
+        let evaluator = BatchEvaluator::new(config.clone()).threads(threads);
+        // The same machines run the second batch, starting afresh.
+        for _ in 0..2 {
+            assert!(
+                evaluator.run(&runs, Some(50)) == serial,
+                "{} threads gave different results",
+                threads
+            );
+        }
+        assert!(evaluator.run(&runs[..1], Some(50)) == serial[..1]);
+    }
+    assert!(execute_batch(&config, &runs, Some(50)) == serial);
+    assert!(execute_batch(&config, &[], None).is_empty());
+}